@@ -0,0 +1,226 @@
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+
+// Failed monitors of a scheduled apply are tried again this often, then given up on
+pub const MAX_APPLY_RETRIES: u32 = 3;
+pub const APPLY_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+// How the backend accepted a wallpaper
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApplyMethod {
+    MonitorId,  // IDesktopWallpaper with the monitor ID matched to the profile entry
+    DeviceName, // IDesktopWallpaper with the profile's device name passed as is
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MonitorApplyResult {
+    pub device: String, // as written in the profile
+    pub path: String, // requested wallpaper as written in the profile
+    pub file: Option<String>, // file handed to the backend after expanding and rendering
+    pub monitor_id: Option<String>,
+    pub method: Option<ApplyMethod>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FitApplyResult {
+    pub position: String,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApplyReport {
+    pub profile: String,
+    pub error: Option<String>, // set when the profile could not be applied at all
+    #[serde(default)]
+    pub cancelled: bool, // a pre_apply hook failed, nothing was changed and retrying will not help
+    pub fit: Option<FitApplyResult>,
+    pub monitors: Vec<MonitorApplyResult>,
+}
+
+impl ApplyReport {
+    pub fn new(profile_name: &str) -> Self {
+        Self {
+            profile: profile_name.to_string(),
+            error: None,
+            cancelled: false,
+            fit: None,
+            monitors: Vec::new(),
+        }
+    }
+
+    pub fn failed(profile_name: &str, error: String) -> Self {
+        Self {
+            error: Some(error),
+            ..Self::new(profile_name)
+        }
+    }
+
+    pub fn cancelled(profile_name: &str, error: String) -> Self {
+        Self {
+            cancelled: true,
+            ..Self::failed(profile_name, error)
+        }
+    }
+
+    pub fn fit_failed(&self) -> bool {
+        self.fit.as_ref().is_some_and(|fit| fit.error.is_some())
+    }
+
+    pub fn failed_devices(&self) -> Vec<String> {
+        self.monitors.iter()
+            .filter(|monitor| monitor.error.is_some())
+            .map(|monitor| monitor.device.clone())
+            .collect()
+    }
+
+    pub fn success(&self) -> bool {
+        self.error.is_none() && !self.fit_failed() && self.monitors.iter().all(|monitor| monitor.error.is_none())
+    }
+
+    // Only monitors and the fit mode are retried, a missing profile or a failed hook stays that way
+    pub fn retryable(&self) -> bool {
+        self.error.is_none() && !self.cancelled && !self.success()
+    }
+
+    // Takes the results of a retry over the ones that failed before
+    pub fn merge(&mut self, retry: ApplyReport) {
+        if retry.fit.is_some() {
+            self.fit = retry.fit;
+        }
+        for result in retry.monitors {
+            match self.monitors.iter_mut().find(|monitor| monitor.device == result.device) {
+                Some(monitor) => *monitor = result,
+                None => self.monitors.push(result),
+            }
+        }
+        self.error = retry.error;
+    }
+
+    // One line for status bars and the window
+    pub fn summary(&self) -> String {
+        if let Some(error) = &self.error {
+            return format!("Profile '{}' was not applied: {}", self.profile, error);
+        }
+
+        let failed = self.failed_devices().len();
+        let applied = self.monitors.len() - failed;
+        let mut summary = format!("Profile '{}': {} of {} monitors set", self.profile, applied, self.monitors.len());
+        if self.fit_failed() {
+            summary.push_str(", fit mode not set");
+        }
+        summary
+    }
+}
+
+// A scheduled apply that still has failed monitors
+#[derive(Debug, Clone)]
+pub struct PendingRetry {
+    pub report: ApplyReport,
+    pub attempts: u32,
+    pub next_attempt: Instant,
+}
+
+impl PendingRetry {
+    pub fn new(report: ApplyReport) -> Self {
+        Self {
+            report,
+            attempts: 0,
+            next_attempt: Instant::now() + APPLY_RETRY_DELAY,
+        }
+    }
+
+    pub fn is_due(&self, now: Instant) -> bool {
+        self.next_attempt <= now
+    }
+
+    // Counts the attempt that produced `report`, false once there is nothing left to retry
+    pub fn record(&mut self, report: ApplyReport, now: Instant) -> bool {
+        self.report = report;
+        self.attempts += 1;
+        self.next_attempt = now + APPLY_RETRY_DELAY;
+        self.report.retryable() && self.attempts < MAX_APPLY_RETRIES
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitor(device: &str, error: Option<&str>) -> MonitorApplyResult {
+        MonitorApplyResult {
+            device: device.to_string(),
+            path: format!("{}.png", device),
+            file: None,
+            monitor_id: None,
+            method: None,
+            error: error.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn retries_replace_the_results_they_cover() {
+        let mut report = ApplyReport::new("work");
+        report.fit = Some(FitApplyResult { position: "fill".to_string(), error: Some("busy".to_string()) });
+        report.monitors = vec![monitor("M1", None), monitor("M2", Some("busy"))];
+        assert_eq!(report.summary(), "Profile 'work': 1 of 2 monitors set, fit mode not set");
+
+        let mut retry = ApplyReport::new("work");
+        retry.fit = Some(FitApplyResult { position: "fill".to_string(), error: None });
+        retry.monitors = vec![monitor("M2", None), monitor("M3", Some("gone"))];
+        report.merge(retry);
+
+        assert_eq!(report.failed_devices(), ["M3"]);
+        assert!(!report.fit_failed());
+        assert_eq!(report.monitors.iter().map(|monitor| monitor.device.as_str()).collect::<Vec<_>>(), ["M1", "M2", "M3"]);
+    }
+
+    #[test]
+    fn a_retry_without_fit_keeps_the_previous_one() {
+        let mut report = ApplyReport::failed("work", "no monitors".to_string());
+        report.fit = Some(FitApplyResult { position: "fill".to_string(), error: None });
+        report.merge(ApplyReport::new("work"));
+        assert!(report.success());
+        assert_eq!(report.fit.unwrap().position, "fill");
+    }
+
+    #[test]
+    fn only_monitor_failures_are_retried() {
+        let mut report = ApplyReport::new("work");
+        report.monitors = vec![monitor("M1", None), monitor("M2", Some("busy"))];
+        assert!(report.retryable());
+
+        report.monitors[1].error = None;
+        assert!(!report.retryable());
+        assert!(!ApplyReport::failed("work", "Profile 'work' not found!".to_string()).retryable());
+        assert!(!ApplyReport::cancelled("work", "pre_apply hook failed".to_string()).retryable());
+    }
+
+    #[test]
+    fn retries_stop_after_success_errors_or_the_last_attempt() {
+        let mut failed = ApplyReport::new("work");
+        failed.monitors = vec![monitor("M1", Some("busy"))];
+
+        let mut pending = PendingRetry::new(failed.clone());
+        let now = Instant::now();
+        assert!(!pending.is_due(now));
+        assert!(pending.is_due(now + APPLY_RETRY_DELAY));
+
+        for attempt in 1..MAX_APPLY_RETRIES {
+            assert!(pending.record(failed.clone(), now), "attempt {}", attempt);
+            assert_eq!(pending.attempts, attempt);
+            assert!(!pending.is_due(now));
+        }
+        assert!(!pending.record(failed.clone(), now));
+
+        let mut pending = PendingRetry::new(failed.clone());
+        let mut retried = ApplyReport::new("work");
+        retried.monitors = vec![monitor("M1", None)];
+        assert!(!pending.record(retried, now));
+
+        // The profile was removed by a reload in between
+        let mut pending = PendingRetry::new(failed);
+        assert!(!pending.record(ApplyReport::failed("work", "Profile 'work' not found!".to_string()), now));
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+// A profile bundle is a zip archive with manifest.toml and the images under images/
+pub const BUNDLE_VERSION: u32 = 1;
+const MANIFEST_NAME: &str = "manifest.toml";
+const IMAGE_DIR: &str = "images";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundleManifest {
+    pub version: u32,
+    pub profile: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fit: Option<String>,
+    #[serde(default)]
+    pub monitors: Vec<BundleMonitor>,
+}
+
+// Device IDs mean nothing on another machine, so each monitor also records where it was
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundleMonitor {
+    pub device: String,
+    pub position: usize, // index with the monitors ordered left to right, then top to bottom
+    pub width: u32,
+    pub height: u32,
+    pub path: String, // "images/..." inside the archive, or a generator spec
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub overlays: Vec<String>,
+}
+
+// Name an image gets inside the archive; the index keeps files with the same name apart
+pub fn archive_name(index: usize, source: &str) -> String {
+    let file_name: String = Path::new(source)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| "wallpaper".to_string())
+        .chars()
+        .map(|c| if c.is_alphanumeric() || c == '.' || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    format!("{}/{}-{}", IMAGE_DIR, index, file_name)
+}
+
+pub fn is_archive_image(path: &str) -> bool {
+    path.starts_with(&format!("{}/", IMAGE_DIR))
+}
+
+// `images` pairs a local file with its name inside the archive
+pub fn write_bundle(output: &Path, manifest: &BundleManifest, images: &[(String, String)]) -> Result<(), String> {
+    let manifest_text = toml::to_string_pretty(manifest).map_err(|e| format!("Failed to write manifest: {}", e))?;
+
+    let file = File::create(output).map_err(|e| format!("Failed to create {}: {}", output.display(), e))?;
+    let mut zip = ZipWriter::new(file);
+    let error = |e: &dyn std::fmt::Display| format!("Failed to write {}: {}", output.display(), e);
+
+    zip.start_file(MANIFEST_NAME, SimpleFileOptions::default()).map_err(|e| error(&e))?;
+    zip.write_all(manifest_text.as_bytes()).map_err(|e| error(&e))?;
+
+    // Images are compressed already, deflating them again only costs time
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    for (source, name) in images {
+        let data = std::fs::read(source).map_err(|e| format!("Failed to read {}: {}", source, e))?;
+        zip.start_file(name.as_str(), stored).map_err(|e| error(&e))?;
+        zip.write_all(&data).map_err(|e| error(&e))?;
+    }
+
+    zip.finish().map_err(|e| error(&e))?;
+    Ok(())
+}
+
+pub fn open_bundle(bundle: &Path) -> Result<(BundleManifest, ZipArchive<File>), String> {
+    let file = File::open(bundle).map_err(|e| format!("Failed to open {}: {}", bundle.display(), e))?;
+    let mut archive = ZipArchive::new(file).map_err(|e| format!("{} is not a profile bundle: {}", bundle.display(), e))?;
+
+    let mut manifest_text = String::new();
+    archive.by_name(MANIFEST_NAME)
+        .map_err(|_| format!("{} has no {}", bundle.display(), MANIFEST_NAME))?
+        .read_to_string(&mut manifest_text)
+        .map_err(|e| format!("Failed to read {}: {}", MANIFEST_NAME, e))?;
+
+    let manifest: BundleManifest = toml::from_str(&manifest_text)
+        .map_err(|e| format!("Invalid {}: {}", MANIFEST_NAME, e.message().trim()))?;
+    if manifest.version > BUNDLE_VERSION {
+        return Err(format!("Bundle version {} was written by a newer version (supported: {})",
+                           manifest.version, BUNDLE_VERSION));
+    }
+
+    Ok((manifest, archive))
+}
+
+// Only plain names below images/ are extracted, so a crafted bundle cannot write elsewhere
+pub fn extract_image(archive: &mut ZipArchive<File>, name: &str, destination: &Path) -> Result<(), String> {
+    let mut entry = archive.by_name(name).map_err(|_| format!("Bundle is missing {}", name))?;
+    match entry.enclosed_name() {
+        Some(path) if path.starts_with(IMAGE_DIR) => {}
+        _ => return Err(format!("Refusing to extract {}", name)),
+    }
+
+    let mut data = Vec::new();
+    entry.read_to_end(&mut data).map_err(|e| format!("Failed to read {}: {}", name, e))?;
+    std::fs::write(destination, data).map_err(|e| format!("Failed to write {}: {}", destination.display(), e))
+}
+
+// Pairs every bundle monitor with a local one, preferring the same position and then the
+// same resolution. `local` is ordered like BundleMonitor::position and holds (device, resolution).
+// Monitors left over on either side stay unmatched.
+pub fn remap_devices(bundle: &[BundleMonitor], local: &[(String, (u32, u32))]) -> Vec<(String, Option<String>)> {
+    let mut ordered: Vec<&BundleMonitor> = bundle.iter().collect();
+    ordered.sort_by_key(|monitor| monitor.position);
+
+    let mut taken = HashSet::new();
+    ordered.into_iter()
+        .map(|monitor| {
+            let best = local.iter()
+                .enumerate()
+                .filter(|(index, _)| !taken.contains(index))
+                .min_by_key(|(index, (_, (width, height)))| (
+                    index.abs_diff(monitor.position),
+                    (*width, *height) != (monitor.width, monitor.height),
+                    (*width as u64 * *height as u64).abs_diff(monitor.width as u64 * monitor.height as u64),
+                ));
+
+            match best {
+                Some((index, (device, _))) => {
+                    taken.insert(index);
+                    (monitor.device.clone(), Some(device.clone()))
+                }
+                None => (monitor.device.clone(), None),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitor(device: &str, position: usize, width: u32, height: u32) -> BundleMonitor {
+        BundleMonitor { device: device.to_string(), position, width, height, path: String::new(), overlays: Vec::new() }
+    }
+
+    fn local(monitors: &[(&str, u32, u32)]) -> Vec<(String, (u32, u32))> {
+        monitors.iter().map(|(device, width, height)| (device.to_string(), (*width, *height))).collect()
+    }
+
+    #[test]
+    fn monitors_are_matched_by_position_first() {
+        let bundle = [monitor("B2", 1, 2560, 1440), monitor("B1", 0, 1920, 1080)];
+        let remapped = remap_devices(&bundle, &local(&[("L1", 1280, 1024), ("L2", 3840, 2160)]));
+        assert_eq!(remapped, [
+            ("B1".to_string(), Some("L1".to_string())),
+            ("B2".to_string(), Some("L2".to_string())),
+        ]);
+    }
+
+    #[test]
+    fn leftover_monitors_are_matched_by_resolution_or_not_at_all() {
+        // The bundle's middle monitor is missing here, so the right one moves left
+        let bundle = [monitor("B1", 0, 1920, 1080), monitor("B2", 1, 2560, 1440), monitor("B3", 2, 1280, 1024)];
+        let remapped = remap_devices(&bundle, &local(&[("L1", 1920, 1080), ("L3", 1280, 1024)]));
+        assert_eq!(remapped, [
+            ("B1".to_string(), Some("L1".to_string())),
+            ("B2".to_string(), Some("L3".to_string())),
+            ("B3".to_string(), None),
+        ]);
+    }
+}
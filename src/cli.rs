@@ -0,0 +1,568 @@
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+use clap::{ArgAction, CommandFactory, Parser, Subcommand};
+use clap_complete::ArgValueCandidates;
+use log::{error, info, warn};
+use crate::completions::{monitor_candidates, print_completion_script, profile_candidates, CompletionShell};
+use crate::config::{backup_path, check_config_file, legacy_config_for, restore_backup, DEFAULT_CONFIG_BACKUPS};
+use crate::config_watcher::ConfigWatcher;
+use crate::daemon::{run_daemon, DaemonClient};
+use crate::diagnostics::{has_errors, print_diagnostics};
+use crate::error::{WallpaperError, WallpaperResult};
+use crate::output::{print_apply_plan, print_apply_report, print_monitors, print_profiles, print_schedule, print_status,
+                    OutputFormat};
+use crate::overlay::Overlay;
+use crate::wallpaper_manager::{DesktopWallpaperPosition, WallpaperManager};
+
+// Exit codes: 0 on success, 1 when the command failed, 2 for invalid usage (clap's default)
+pub const EXIT_SUCCESS: i32 = 0;
+pub const EXIT_FAILURE: i32 = 1;
+
+#[derive(Parser)]
+#[command(name = "wallpaper-helper", version, about = "Per-monitor wallpaper profiles", long_about = None)]
+pub struct Cli {
+    /// Config file to use instead of the per-user one
+    #[arg(long, global = true, value_name = "FILE")]
+    pub config: Option<String>,
+
+    /// Output format of the listing commands and apply --dry-run
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Table)]
+    pub format: OutputFormat,
+
+    /// Show debug messages, twice for everything
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Only show errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Also write messages to FILE_rCURRENT.EXT, rotated to FILE_r00000.EXT and so on at 1 MB
+    #[arg(long, global = true, value_name = "FILE")]
+    pub log_file: Option<String>,
+
+    /// Without a command the window opens
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// List the monitors and their current wallpapers
+    Monitors,
+    /// Apply a profile to all monitors, through the daemon if one is running
+    Apply {
+        #[arg(add = ArgValueCandidates::new(profile_candidates))]
+        profile: String,
+        /// Show what would change on each monitor without changing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Make the daemon apply the profile after the one it applied last
+    Next,
+    /// Show what the daemon is doing
+    Status,
+    /// Keep running the scheduler and take commands over a local socket and D-Bus
+    Daemon {
+        /// Register on this D-Bus address instead of the session bus, e.g. a private dbus-daemon
+        #[arg(long, value_name = "ADDRESS")]
+        dbus_address: Option<String>,
+    },
+    /// Create, edit and inspect profiles
+    #[command(subcommand)]
+    Profile(ProfileCommand),
+    /// Switch profiles at fixed times of day
+    #[command(subcommand)]
+    Schedule(ScheduleCommand),
+    /// Validate or restore the config file
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Configure the color theme written after each apply
+    #[command(subcommand)]
+    Theme(ThemeCommand),
+    /// Find near-duplicate images in the profiles and the given folders
+    Duplicates {
+        /// Point profile entries at the image kept from each group
+        #[arg(long)]
+        rewrite: bool,
+        /// Number of differing hash bits (0-64) that still count as a duplicate
+        #[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u32).range(0..=64))]
+        threshold: u32,
+        folders: Vec<String>,
+    },
+    /// Rank the images in a folder by how well they fit each monitor
+    Suggest {
+        folder: String,
+        #[arg(default_value_t = 5)]
+        count: usize,
+    },
+    /// Print the script that makes the shell complete commands, profile names and monitor IDs,
+    /// e.g. `source <(wallpaper-helper completions bash)`
+    Completions {
+        #[arg(value_enum)]
+        shell: CompletionShell,
+    },
+    /// Print the man page, or write one for every command into a directory
+    Man {
+        #[arg(long, value_name = "DIR")]
+        dir: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ProfileCommand {
+    /// List all profiles
+    List,
+    /// Create an empty profile
+    Create {
+        name: String,
+        /// Inherit everything that is not overridden from this profile
+        #[arg(long, value_name = "PROFILE", add = ArgValueCandidates::new(profile_candidates))]
+        extends: Option<String>,
+    },
+    /// Set the wallpaper of one monitor in a profile
+    Set {
+        #[arg(add = ArgValueCandidates::new(profile_candidates))]
+        profile: String,
+        #[arg(add = ArgValueCandidates::new(monitor_candidates))]
+        monitor: String,
+        path: String,
+        /// Store the path relative to the wallpaper root
+        #[arg(long)]
+        relative: bool,
+    },
+    /// Set how the wallpapers are positioned, or clear it with "none"
+    Fit {
+        #[arg(add = ArgValueCandidates::new(profile_candidates))]
+        profile: String,
+        mode: String,
+    },
+    /// Inherit from another profile, or stop inheriting with "none"
+    Extends {
+        #[arg(add = ArgValueCandidates::new(profile_candidates))]
+        profile: String,
+        #[arg(add = ArgValueCandidates::new(profile_candidates))]
+        parent: String,
+    },
+    /// Print a profile
+    Show {
+        #[arg(add = ArgValueCandidates::new(profile_candidates))]
+        name: String,
+        /// Include inherited values and where they come from
+        #[arg(long)]
+        resolved: bool,
+    },
+    /// Write a profile and its images into a bundle
+    Export {
+        #[arg(add = ArgValueCandidates::new(profile_candidates))]
+        name: String,
+        bundle: String,
+    },
+    /// Add the profile from a bundle
+    Import {
+        bundle: String,
+        /// Import under a different name
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// Draw the hostname, the date, a quote or a calendar onto a monitor's wallpaper
+    #[command(subcommand)]
+    Overlay(OverlayCommand),
+}
+
+#[derive(Subcommand)]
+pub enum OverlayCommand {
+    /// Add an overlay, e.g. "date:%A;size=48;position=top-left;color=#ffcc00"
+    Add {
+        #[arg(add = ArgValueCandidates::new(profile_candidates))]
+        profile: String,
+        #[arg(add = ArgValueCandidates::new(monitor_candidates))]
+        monitor: String,
+        spec: String,
+    },
+    /// Remove all overlays of one monitor
+    Clear {
+        #[arg(add = ArgValueCandidates::new(profile_candidates))]
+        profile: String,
+        #[arg(add = ArgValueCandidates::new(monitor_candidates))]
+        monitor: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ScheduleCommand {
+    /// List the schedule
+    List,
+    /// Apply a profile every day at the given time
+    Add {
+        #[arg(add = ArgValueCandidates::new(profile_candidates))]
+        profile: String,
+        #[arg(value_parser = clap::value_parser!(u32).range(0..24))]
+        hour: u32,
+        #[arg(value_parser = clap::value_parser!(u32).range(0..60))]
+        minute: u32,
+    },
+    /// Run the scheduler in the foreground until interrupted
+    Run,
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Check a config file and print its problems
+    Check {
+        file: Option<String>,
+    },
+    /// Make the daemon load the config again
+    Reload,
+    /// Replace the config with backup <n> (1 is the newest)
+    Restore {
+        #[arg(value_parser = clap::value_parser!(u64).range(1..))]
+        n: u64,
+        file: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ThemeCommand {
+    /// Manage the templates filled with the palette, e.g. {color0} or {wallpaper}
+    #[command(subcommand)]
+    Template(TemplateCommand),
+}
+
+#[derive(Subcommand)]
+pub enum TemplateCommand {
+    /// Render <template> into <output> after each apply
+    Add {
+        template: String,
+        output: String,
+    },
+}
+
+fn exit_code(success: bool) -> i32 {
+    if success { EXIT_SUCCESS } else { EXIT_FAILURE }
+}
+
+// Errors are printed once here instead of by every manager method
+fn finish(result: WallpaperResult<bool>) -> i32 {
+    match result {
+        Ok(success) => exit_code(success),
+        Err(e) => {
+            error!("{}", e);
+            EXIT_FAILURE
+        }
+    }
+}
+
+// A missing config is fine, e.g. before the first profile exists; a broken one is not
+fn load_manager(config_file: &str) -> WallpaperResult<WallpaperManager> {
+    let mut manager = WallpaperManager::new();
+    let exists = Path::new(config_file).exists() || legacy_config_for(config_file).is_some();
+    if exists {
+        manager.load_config(config_file)?;
+    }
+    Ok(manager)
+}
+
+// Loads the config, runs `change` and saves only if it succeeded
+fn modify(config_file: &str, change: impl FnOnce(&mut WallpaperManager) -> WallpaperResult<()>) -> i32 {
+    finish(load_manager(config_file).and_then(|mut manager| {
+        change(&mut manager)?;
+        manager.save_config(config_file)?;
+        Ok(true)
+    }))
+}
+
+// `query` returns false for failures it has already reported, like monitors a profile could not be applied to
+fn inspect(config_file: &str, query: impl FnOnce(&mut WallpaperManager) -> WallpaperResult<bool>) -> i32 {
+    finish(load_manager(config_file).and_then(|mut manager| query(&mut manager)))
+}
+
+fn connect_daemon() -> WallpaperResult<DaemonClient> {
+    DaemonClient::connect().ok_or_else(|| WallpaperError::Daemon("No daemon is running, start one with `wallpaper-helper daemon`".to_string()))
+}
+
+fn print_man_pages(dir: Option<&str>) -> WallpaperResult<()> {
+    match dir {
+        Some(dir) => {
+            std::fs::create_dir_all(dir).map_err(|e| WallpaperError::io(dir, e))?;
+            clap_mangen::generate_to(Cli::command(), dir).map_err(|e| WallpaperError::io(dir, e))?;
+            info!("Wrote the man pages to {}", dir);
+            Ok(())
+        }
+        None => clap_mangen::Man::new(Cli::command()).render(&mut std::io::stdout())
+            .map_err(|e| WallpaperError::io("stdout", e)),
+    }
+}
+
+// "none" clears optional settings like the fit mode or the parent profile
+fn optional_value(value: &str) -> Option<&str> {
+    if value.eq_ignore_ascii_case("none") { None } else { Some(value) }
+}
+
+// Commands that run without opening the window, returns the process exit code
+pub fn run(command: Command, config_file: &str, format: OutputFormat) -> i32 {
+    match command {
+        Command::Monitors => inspect(config_file, |manager| {
+            print_monitors(&manager.list_monitors(), format);
+            Ok(true)
+        }),
+        Command::Apply { profile, dry_run: false } => {
+            let report = match DaemonClient::connect() {
+                Some(mut daemon) => daemon.apply(&profile),
+                None => load_manager(config_file).map(|mut manager| {
+                    let report = manager.apply_profile(&profile);
+                    manager.wait_for_hooks();
+                    report
+                }),
+            };
+            finish(report.map(|report| {
+                print_apply_report(&report, format);
+                report.success()
+            }))
+        }
+        Command::Apply { profile, dry_run: true } => inspect(config_file, |manager| {
+            let plan = manager.plan_profile(&profile)?;
+            print_apply_plan(&plan, format);
+            Ok(plan.monitors.iter().all(|entry| entry.problem.is_none()))
+        }),
+        Command::Next => finish(connect_daemon().and_then(|mut daemon| daemon.next_profile()).map(|report| {
+            print_apply_report(&report, format);
+            report.success()
+        })),
+        Command::Status => finish(connect_daemon().and_then(|mut daemon| daemon.status()).map(|status| {
+            print_status(&status, format);
+            true
+        })),
+        Command::Daemon { dbus_address } => finish(load_manager(config_file)
+            .and_then(|manager| run_daemon(manager, config_file, dbus_address.as_deref()))
+            .map(|_| true)),
+        Command::Profile(command) => run_profile(command, config_file, format),
+        Command::Schedule(command) => run_schedule(command, config_file, format),
+        Command::Config(command) => run_config(command, config_file),
+        Command::Theme(ThemeCommand::Template(TemplateCommand::Add { template, output })) => {
+            modify(config_file, |manager| manager.add_theme_template(&template, &output))
+        }
+        Command::Duplicates { rewrite, threshold, folders } => run_duplicates(rewrite, threshold, &folders, config_file),
+        Command::Suggest { folder, count } => run_suggest(&folder, count),
+        Command::Completions { shell } => finish(print_completion_script(shell).map(|_| true)),
+        Command::Man { dir } => finish(print_man_pages(dir.as_deref()).map(|_| true)),
+    }
+}
+
+fn run_profile(command: ProfileCommand, config_file: &str, format: OutputFormat) -> i32 {
+    match command {
+        ProfileCommand::List => inspect(config_file, |manager| {
+            print_profiles(&manager.list_profiles(), format);
+            Ok(true)
+        }),
+        ProfileCommand::Create { name, extends } => modify(config_file, |manager| {
+            manager.create_profile(&name)?;
+            if extends.is_some() {
+                manager.set_profile_parent(&name, extends.as_deref())?;
+            }
+            Ok(())
+        }),
+        ProfileCommand::Set { profile, monitor, path, relative } => modify(config_file, |manager| {
+            manager.set_wallpaper_in_profile(&profile, &monitor, &path, relative)
+        }),
+        ProfileCommand::Fit { profile, mode } => {
+            let fit = match optional_value(&mode) {
+                Some(mode) => match DesktopWallpaperPosition::from_name(mode) {
+                    Some(fit) => Some(fit),
+                    None => {
+                        error!("Unknown fit mode '{}', use center, tile, stretch, fit, fill, span or none", mode);
+                        return EXIT_FAILURE;
+                    }
+                },
+                None => None,
+            };
+            modify(config_file, |manager| manager.set_fit_in_profile(&profile, fit))
+        }
+        ProfileCommand::Extends { profile, parent } => modify(config_file, |manager| {
+            manager.set_profile_parent(&profile, optional_value(&parent))
+        }),
+        ProfileCommand::Show { name, resolved } => inspect(config_file, |manager| {
+            print_profile(manager, &name, resolved)?;
+            Ok(true)
+        }),
+        ProfileCommand::Export { name, bundle } => inspect(config_file, |manager| {
+            manager.export_profile(&name, &bundle)?;
+            Ok(true)
+        }),
+        ProfileCommand::Import { bundle, name } => modify(config_file, |manager| {
+            manager.import_profile(&bundle, name.as_deref())
+        }),
+        ProfileCommand::Overlay(OverlayCommand::Add { profile, monitor, spec }) => modify(config_file, |manager| {
+            let overlay = Overlay::parse(&spec).map_err(WallpaperError::InvalidProfile)?;
+            manager.add_overlay_to_profile(&profile, &monitor, overlay)
+        }),
+        ProfileCommand::Overlay(OverlayCommand::Clear { profile, monitor }) => modify(config_file, |manager| {
+            manager.clear_overlays_in_profile(&profile, &monitor)
+        }),
+    }
+}
+
+// Prints a profile's own entries, or with `resolved` everything it inherits and where it came from
+fn print_profile(manager: &WallpaperManager, profile_name: &str, resolved: bool) -> WallpaperResult<()> {
+    let resolved_profile = manager.resolve_profile(profile_name)?;
+    let profile = if resolved { &resolved_profile.profile } else { &manager.profiles[profile_name] };
+
+    // Own values are not annotated unless the profile inherits anything at all
+    let origin = |source: Option<&String>| match source {
+        Some(source) if resolved && resolved_profile.chain.len() > 1 => format!("  (from {})", source),
+        _ => String::new(),
+    };
+
+    println!("Profile '{}'", profile_name);
+    if resolved && resolved_profile.chain.len() > 1 {
+        println!("  chain: {}", resolved_profile.chain.join(" -> "));
+    } else if let Some(parent) = &profile.extends {
+        println!("  extends: {}", parent);
+    }
+
+    if let Some(fit) = profile.fit {
+        println!("  fit: {:?}{}", fit, origin(resolved_profile.fit_source.as_ref()));
+    }
+
+    let mut devices: Vec<&String> = profile.monitor_wallpapers.keys().chain(profile.overlays.keys()).collect();
+    devices.sort();
+    devices.dedup();
+
+    for device in devices {
+        match profile.monitor_wallpapers.get(device) {
+            Some(wallpaper) => println!("  {} = {}{}", device, wallpaper,
+                                        origin(resolved_profile.wallpaper_sources.get(device))),
+            None => println!("  {}", device),
+        }
+        for overlay in profile.overlays.get(device).into_iter().flatten() {
+            println!("      overlay: {}{}", overlay.to_spec(), origin(resolved_profile.overlay_sources.get(device)));
+        }
+    }
+
+    Ok(())
+}
+
+fn run_schedule(command: ScheduleCommand, config_file: &str, format: OutputFormat) -> i32 {
+    match command {
+        ScheduleCommand::List => inspect(config_file, |manager| {
+            print_schedule(&manager.list_schedule(), format);
+            Ok(true)
+        }),
+        ScheduleCommand::Add { profile, hour, minute } => modify(config_file, |manager| {
+            manager.add_schedule(&profile, hour, minute)
+        }),
+        ScheduleCommand::Run => {
+            // The daemon already applies the schedule, a second scheduler would apply every entry twice
+            if DaemonClient::connect().is_some() {
+                return finish(Err(WallpaperError::Daemon(
+                    "The daemon is running and already applies the schedule".to_string())));
+            }
+
+            let mut manager = match load_manager(config_file) {
+                Ok(manager) => manager,
+                Err(e) => return finish(Err(e)),
+            };
+
+            let mut watcher = ConfigWatcher::new(&manager.config_watch_paths(config_file));
+            if let Err(e) = &watcher {
+                warn!("Not watching {} for changes: {}", config_file, e);
+            }
+
+            manager.start_scheduler();
+            info!("Press Ctrl+C to stop.");
+            loop {
+                thread::sleep(Duration::from_secs(1));
+
+                if let Ok(watcher) = &mut watcher {
+                    if watcher.changed() {
+                        if let Err(e) = manager.reload_config(config_file) {
+                            error!("Reload failed, still using the previous configuration: {}", e);
+                        }
+                        if let Err(e) = watcher.update(&manager.config_watch_paths(config_file)) {
+                            warn!("Not watching all of {} for changes: {}", config_file, e);
+                        }
+                    }
+                }
+                for report in manager.apply_due_profiles() {
+                    print_apply_report(&report, format);
+                }
+            }
+        }
+    }
+}
+
+fn run_config(command: ConfigCommand, config_file: &str) -> i32 {
+    match command {
+        ConfigCommand::Check { file } => {
+            let filename = file.as_deref().unwrap_or(config_file);
+            let (_, diagnostics) = check_config_file(filename);
+            print_diagnostics(&diagnostics);
+
+            exit_code(!has_errors(&diagnostics))
+        }
+        ConfigCommand::Reload => finish(connect_daemon().and_then(|mut daemon| daemon.reload()).map(|_| true)),
+        ConfigCommand::Restore { n, file } => {
+            let n = n as usize;
+            let filename = file.as_deref().unwrap_or(config_file);
+
+            // Keep as many backups as the current config asks for, it is only read here
+            let backups = match check_config_file(filename) {
+                (Some(loaded), _) => loaded.document.backups.unwrap_or(DEFAULT_CONFIG_BACKUPS),
+                (None, _) => {
+                    warn!("Cannot read {}, keeping the default of {} backups", filename, DEFAULT_CONFIG_BACKUPS);
+                    DEFAULT_CONFIG_BACKUPS
+                }
+            };
+
+            match restore_backup(filename, n, backups) {
+                Ok(_) => {
+                    println!("Restored {} from {}", filename, backup_path(filename, n).display());
+                    EXIT_SUCCESS
+                }
+                Err(diagnostics) => {
+                    print_diagnostics(&diagnostics);
+                    error!("Backup {} was not restored.", n);
+                    EXIT_FAILURE
+                }
+            }
+        }
+    }
+}
+
+fn run_duplicates(rewrite: bool, threshold: u32, folders: &[String], config_file: &str) -> i32 {
+    let mut manager = match load_manager(config_file) {
+        Ok(manager) => manager,
+        Err(e) => return finish(Err(e)),
+    };
+
+    let clusters = manager.find_duplicates(folders, threshold);
+    manager.print_duplicates(&clusters);
+
+    if rewrite && !clusters.is_empty() {
+        let rewritten = manager.rewrite_duplicates(&clusters);
+        println!("Rewrote {} profile entries.", rewritten);
+        if rewritten > 0 {
+            if let Err(e) = manager.save_config(config_file) {
+                return finish(Err(e));
+            }
+        }
+    }
+
+    EXIT_SUCCESS
+}
+
+fn run_suggest(folder: &str, count: usize) -> i32 {
+    let manager = WallpaperManager::new();
+    for (device_name, ranked) in manager.suggest_best_match(folder) {
+        println!("{}:", device_name);
+        if ranked.is_empty() {
+            println!("   No images found in {}", folder);
+        }
+        for (path, score) in ranked.iter().take(count) {
+            println!("   {:>3.0}%  {}", score * 100.0, path);
+        }
+    }
+
+    EXIT_SUCCESS
+}
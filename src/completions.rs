@@ -0,0 +1,88 @@
+use std::io;
+use std::path::Path;
+use clap::{CommandFactory, ValueEnum};
+use clap_complete::env::{Bash, EnvCompleter, Fish, Powershell, Zsh};
+use clap_complete::{CompleteEnv, CompletionCandidate};
+use crate::cli::Cli;
+use crate::config::{check_config_file, resolve_config_path};
+use crate::error::{WallpaperError, WallpaperResult};
+use crate::wallpaper_manager::WallpaperManager;
+
+// The shell calls `COMPLETE=<shell> wallpaper-helper -- <words>` to ask for candidates
+pub const COMPLETE_ENV_VAR: &str = "COMPLETE";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CompletionShell {
+    Bash,
+    Zsh,
+    Fish,
+    Powershell,
+}
+
+// Answers a completion request and exits, returns right away on every other run
+pub fn complete_if_requested() {
+    CompleteEnv::with_factory(Cli::command).var(COMPLETE_ENV_VAR).complete();
+}
+
+// The script only registers this executable as the completer, the candidates are
+// looked up on every tab press so they follow the config and the connected monitors
+pub fn print_completion_script(shell: CompletionShell) -> WallpaperResult<()> {
+    let completer = std::env::current_exe().map_err(|e| WallpaperError::io("current executable", e))?;
+    let bin = completer.file_stem().map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_else(|| Cli::command().get_name().to_string());
+
+    let shell: &dyn EnvCompleter = match shell {
+        CompletionShell::Bash => &Bash,
+        CompletionShell::Zsh => &Zsh,
+        CompletionShell::Fish => &Fish,
+        CompletionShell::Powershell => &Powershell,
+    };
+    shell.write_registration(COMPLETE_ENV_VAR, Cli::command().get_name(), &bin, &completer.to_string_lossy(),
+                             &mut io::stdout())
+        .map_err(|e| WallpaperError::io("stdout", e))
+}
+
+// The completion request carries the words typed so far, so a --config given there is honoured
+fn completion_config_path() -> String {
+    let args: Vec<String> = std::env::args().collect();
+    let explicit = args.windows(2)
+        .find(|pair| pair[0] == "--config")
+        .map(|pair| pair[1].clone())
+        .or_else(|| args.iter().find_map(|arg| arg.strip_prefix("--config=").map(|path| path.to_string())));
+    resolve_config_path(explicit.as_deref())
+}
+
+// Nothing is logged while completing, a broken config just completes nothing
+pub fn profile_candidates() -> Vec<CompletionCandidate> {
+    let config_file = completion_config_path();
+    if !Path::new(&config_file).exists() {
+        return Vec::new();
+    }
+
+    let (loaded, _) = check_config_file(&config_file);
+    let mut profiles: Vec<_> = loaded.map(|loaded| loaded.profiles.into_values().collect()).unwrap_or_default();
+    profiles.sort_by(|a, b| a.name.cmp(&b.name));
+
+    profiles.into_iter()
+        .map(|profile| {
+            let help = match &profile.extends {
+                Some(parent) => format!("{} monitors, extends {}", profile.monitor_wallpapers.len(), parent),
+                None => format!("{} monitors", profile.monitor_wallpapers.len()),
+            };
+            CompletionCandidate::new(profile.name).help(Some(help.into()))
+        })
+        .collect()
+}
+
+// Saves copying the long \\?\DISPLAY#... IDs out of `monitors` by hand
+pub fn monitor_candidates() -> Vec<CompletionCandidate> {
+    WallpaperManager::new().list_monitors().into_iter()
+        .map(|monitor| {
+            let mut help = format!("{}x{} at {},{}", monitor.rect.width, monitor.rect.height, monitor.rect.x, monitor.rect.y);
+            if monitor.primary {
+                help.push_str(", primary");
+            }
+            CompletionCandidate::new(monitor.id).help(Some(help.into()))
+        })
+        .collect()
+}
@@ -0,0 +1,661 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use toml::Spanned;
+use toml_edit::{DocumentMut, Item, Table, Value};
+use crate::diagnostics::{has_errors, line_column, Diagnostic, Severity};
+use crate::error::{WallpaperError, WallpaperResult};
+use crate::generator::{is_generator_spec, GeneratorSpec};
+use crate::hooks::Hooks;
+use crate::includes::{check_included_document, include_files, is_same_file, merge_profile, profiles_for_file,
+                      ConfigSources, IncludedFile};
+use crate::inheritance::parent_chain;
+use crate::legacy_format::parse_legacy_config;
+use crate::overlay::Overlay;
+use crate::paths::PathContext;
+use crate::wallpaper_manager::{DesktopWallpaperPosition, ScheduleEntry, WallpaperProfile};
+
+pub const CONFIG_VERSION: u32 = 1;
+pub const LEGACY_CONFIG_NAME: &str = "config.txt";
+pub const DEFAULT_CONFIG_BACKUPS: usize = 5;
+pub const CONFIG_FILE_NAME: &str = "config.toml";
+pub const CONFIG_ENV_VAR: &str = "WALLPAPER_HELPER_CONFIG";
+const APP_DIR_NAME: &str = "wallpaper-helper";
+
+fn default_version() -> u32 {
+    CONFIG_VERSION
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+// Keys this version does not know about end up in the `extra` tables and are
+// written back unchanged, so newer configs survive a round trip through older builds.
+// Values that can be invalid keep their position for diagnostics.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConfigFile {
+    #[serde(default = "default_version")]
+    pub version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backups: Option<usize>, // how many old versions save_config keeps as config.toml.1..N
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wallpaper_root: Option<String>, // base for relative wallpaper paths, may use ~ and $VARS
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>, // more files with [profiles], read before profiles.d and this file
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub templates: Vec<TemplateConfig>,
+    #[serde(default, skip_serializing_if = "HooksConfig::is_empty")]
+    pub hooks: HooksConfig, // run around every profile, before the profile's own hooks
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub schedule: Vec<ScheduleConfig>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, ProfileConfig>,
+    #[serde(flatten)]
+    pub extra: toml::Table,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProfileConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extends: Option<Spanned<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fit: Option<Spanned<String>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub monitors: BTreeMap<String, MonitorConfig>,
+    #[serde(default, skip_serializing_if = "HooksConfig::is_empty")]
+    pub hooks: HooksConfig,
+    #[serde(flatten)]
+    pub extra: toml::Table,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MonitorConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<Spanned<String>>, // may be left out to only override the inherited overlays
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub overlays: Vec<Spanned<String>>,
+    #[serde(flatten)]
+    pub extra: toml::Table,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduleConfig {
+    pub profile: Spanned<String>,
+    pub hour: Spanned<u32>,
+    pub minute: Spanned<u32>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(flatten)]
+    pub extra: toml::Table,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TemplateConfig {
+    pub template: Spanned<String>,
+    pub output: String,
+    #[serde(flatten)]
+    pub extra: toml::Table,
+}
+
+// Shell commands, each one run with `sh -c` (`cmd /C` on Windows)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HooksConfig {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pre_apply: Vec<Spanned<String>>, // a command exiting non-zero cancels the apply
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_apply: Vec<Spanned<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<Spanned<u64>>, // seconds per command
+    #[serde(flatten)]
+    pub extra: toml::Table,
+}
+
+impl HooksConfig {
+    pub fn is_empty(&self) -> bool {
+        self.pre_apply.is_empty() && self.post_apply.is_empty() && self.timeout.is_none() && self.extra.is_empty()
+    }
+}
+
+// Values created in memory have no position in any file
+pub fn unspanned<T>(value: T) -> Spanned<T> {
+    Spanned::new(0..0, value)
+}
+
+// Everything a config file defines, built up completely before it replaces the current state
+#[derive(Debug, Clone, Default)]
+pub struct LoadedConfig {
+    pub profiles: HashMap<String, WallpaperProfile>,
+    pub schedule: Vec<ScheduleEntry>,
+    pub theme_templates: Vec<(String, String)>,
+    pub hooks: Hooks, // the global [hooks]
+    pub document: ConfigFile,
+    pub paths: PathContext,
+    pub sources: ConfigSources,
+}
+
+pub fn is_legacy_config(filename: &str) -> bool {
+    Path::new(filename)
+        .extension()
+        .map(|ext| ext.eq_ignore_ascii_case("txt"))
+        .unwrap_or(false)
+}
+
+// %APPDATA%\wallpaper-helper on Windows, $XDG_CONFIG_HOME/wallpaper-helper (or ~/.config) elsewhere
+pub fn config_dir() -> Option<PathBuf> {
+    let non_empty = |name: &str| std::env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from);
+
+    let base = if cfg!(windows) {
+        non_empty("APPDATA")
+    } else {
+        non_empty("XDG_CONFIG_HOME").or_else(|| non_empty("HOME").map(|home| home.join(".config")))
+    };
+    base.map(|base| base.join(APP_DIR_NAME))
+}
+
+// Rendered wallpapers and theme files: %LOCALAPPDATA%\wallpaper-helper on Windows,
+// $XDG_CACHE_HOME/wallpaper-helper (or ~/.cache) elsewhere. The shared temp dir is the last resort
+// and gets the user name, so that users do not render into each other's files.
+pub fn cache_dir() -> PathBuf {
+    let non_empty = |name: &str| std::env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from);
+
+    let base = if cfg!(windows) {
+        non_empty("LOCALAPPDATA")
+    } else {
+        non_empty("XDG_CACHE_HOME").or_else(|| non_empty("HOME").map(|home| home.join(".cache")))
+    };
+    match base {
+        Some(base) => base.join(APP_DIR_NAME),
+        None => {
+            let user = std::env::var("USERNAME").or_else(|_| std::env::var("USER")).unwrap_or_default();
+            std::env::temp_dir().join(format!("{}-{}", APP_DIR_NAME, user))
+        }
+    }
+}
+
+// --config wins over WALLPAPER_HELPER_CONFIG, which wins over the per-user config directory.
+// Without any of them the working directory is used like before.
+pub fn resolve_config_path(explicit: Option<&str>) -> String {
+    if let Some(path) = explicit {
+        return path.to_string();
+    }
+
+    if let Some(path) = std::env::var_os(CONFIG_ENV_VAR).filter(|value| !value.is_empty()) {
+        return path.to_string_lossy().to_string();
+    }
+
+    match config_dir() {
+        Some(dir) => dir.join(CONFIG_FILE_NAME).to_string_lossy().to_string(),
+        None => CONFIG_FILE_NAME.to_string(),
+    }
+}
+
+// A config.txt next to the new file, or one in the working directory where older
+// versions kept it, is migrated as long as the new file does not exist yet
+pub fn legacy_config_for(filename: &str) -> Option<PathBuf> {
+    if Path::new(filename).exists() {
+        return None;
+    }
+
+    [Path::new(filename).with_file_name(LEGACY_CONFIG_NAME), PathBuf::from(LEGACY_CONFIG_NAME)]
+        .into_iter()
+        .find(|legacy| legacy.exists())
+}
+
+// Problems with a single profile entry that both config formats report the same way
+pub fn check_wallpaper_path(paths: &PathContext, wallpaper_path: &str) -> Option<(Severity, String)> {
+    if is_generator_spec(wallpaper_path) {
+        return GeneratorSpec::parse(wallpaper_path).err().map(|e| (Severity::Error, e));
+    }
+
+    // Variables may only be set on some machines, so neither case stops the config from loading
+    match paths.expand(wallpaper_path) {
+        Ok(expanded) if !Path::new(&expanded).exists() => {
+            Some((Severity::Warning, format!("wallpaper file not found: {}", expanded)))
+        }
+        Ok(_) => None,
+        Err(e) => Some((Severity::Warning, e)),
+    }
+}
+
+pub fn check_schedule_time(hour: u32, minute: u32) -> Option<String> {
+    if hour > 23 || minute > 59 {
+        Some(format!("invalid time {:02}:{:02}, use 0-23 for hours and 0-59 for minutes", hour, minute))
+    } else {
+        None
+    }
+}
+
+pub fn check_schedule_profiles(filename: &str, loaded: &LoadedConfig, lines: &[(usize, usize)], diagnostics: &mut Vec<Diagnostic>) {
+    for (entry, (line, column)) in loaded.schedule.iter().zip(lines) {
+        if !loaded.profiles.contains_key(&entry.profile_name) {
+            diagnostics.push(Diagnostic::warning(filename, *line, *column,
+                                                 format!("schedule refers to unknown profile '{}'", entry.profile_name)));
+        }
+    }
+}
+
+// `lines` holds the file and position of each profile's extends value
+pub fn check_profile_parents(filename: &str, loaded: &LoadedConfig, lines: &HashMap<String, (String, usize, usize)>,
+                             diagnostics: &mut Vec<Diagnostic>) {
+    let mut names: Vec<&String> = loaded.profiles.keys().collect();
+    names.sort();
+
+    for name in names {
+        if loaded.profiles[name].extends.is_none() {
+            continue;
+        }
+
+        if let Err(e) = parent_chain(&loaded.profiles, name) {
+            match lines.get(name) {
+                Some((file, line, column)) => diagnostics.push(Diagnostic::error(file, *line, *column, e)),
+                None => diagnostics.push(Diagnostic::error(filename, 0, 0, e)),
+            }
+        }
+    }
+}
+
+fn parse_toml_document(filename: &str, source: &str, diagnostics: &mut Vec<Diagnostic>) -> Option<ConfigFile> {
+    let config: ConfigFile = match toml::from_str(source) {
+        Ok(config) => config,
+        Err(e) => {
+            let offset = e.span().map(|span| span.start).unwrap_or(0);
+            diagnostics.push(Diagnostic::at_offset(filename, source, offset, Severity::Error, e.message().trim()));
+            return None;
+        }
+    };
+
+    // Keys this version does not know are kept, so the config is still loaded and saved
+    if config.version > CONFIG_VERSION {
+        diagnostics.push(Diagnostic::warning(filename, 0, 0,
+                                             format!("config version {} was written by a newer version (supported: {}), \
+                                                      settings this version does not know are ignored",
+                                                     config.version, CONFIG_VERSION)));
+    }
+
+    Some(config)
+}
+
+fn parse_hooks(filename: &str, source: &str, config: &HooksConfig, diagnostics: &mut Vec<Diagnostic>) -> Hooks {
+    let mut hooks = Hooks::default();
+
+    for (commands, parsed) in [(&config.pre_apply, &mut hooks.pre_apply), (&config.post_apply, &mut hooks.post_apply)] {
+        for command in commands {
+            if command.get_ref().trim().is_empty() {
+                diagnostics.push(Diagnostic::at_offset(filename, source, command.span().start, Severity::Error,
+                                                       "empty hook command"));
+                continue;
+            }
+            parsed.push(command.get_ref().clone());
+        }
+    }
+
+    if let Some(timeout) = &config.timeout {
+        if *timeout.get_ref() == 0 {
+            diagnostics.push(Diagnostic::at_offset(filename, source, timeout.span().start, Severity::Error,
+                                                   "hook timeout must be at least 1 second"));
+        } else {
+            hooks.timeout = Some(*timeout.get_ref());
+        }
+    }
+
+    hooks
+}
+
+// Turns the [profiles] of one file into profiles; `parent_lines` collects where each extends is
+fn parse_profiles(filename: &str, source: &str, config: &ConfigFile, paths: &PathContext,
+                  parent_lines: &mut HashMap<String, (String, usize, usize)>,
+                  diagnostics: &mut Vec<Diagnostic>) -> Vec<WallpaperProfile> {
+    let at = |offset: usize, severity: Severity, message: String| {
+        Diagnostic::at_offset(filename, source, offset, severity, message)
+    };
+
+    let mut profiles = Vec::new();
+    for (name, profile_config) in &config.profiles {
+        let mut profile = WallpaperProfile::new(name);
+
+        if let Some(extends) = &profile_config.extends {
+            profile.extends = Some(extends.get_ref().clone());
+            let (line, column) = line_column(source, extends.span().start);
+            parent_lines.insert(name.clone(), (filename.to_string(), line, column));
+        }
+
+        if let Some(fit) = &profile_config.fit {
+            profile.fit = DesktopWallpaperPosition::from_name(fit.get_ref());
+            if profile.fit.is_none() {
+                diagnostics.push(at(fit.span().start, Severity::Error,
+                                    format!("unknown fit mode '{}' in profile '{}'", fit.get_ref(), name)));
+            }
+        }
+
+        profile.hooks = parse_hooks(filename, source, &profile_config.hooks, diagnostics);
+
+        for (device, monitor) in &profile_config.monitors {
+            if let Some(path) = &monitor.path {
+                if let Some((severity, message)) = check_wallpaper_path(paths, path.get_ref()) {
+                    diagnostics.push(at(path.span().start, severity, message));
+                }
+                profile.monitor_wallpapers.insert(device.clone(), path.get_ref().clone());
+            }
+
+            for spec in &monitor.overlays {
+                match Overlay::parse(spec.get_ref()) {
+                    Ok(overlay) => profile.overlays.entry(device.clone()).or_default().push(overlay),
+                    Err(e) => diagnostics.push(at(spec.span().start, Severity::Error, e)),
+                }
+            }
+        }
+
+        profiles.push(profile);
+    }
+
+    profiles
+}
+
+// Reads every included file and drop-in, lowest precedence first, and merges their profiles
+fn load_included_profiles(filename: &str, config: &ConfigFile, loaded: &mut LoadedConfig,
+                          parent_lines: &mut HashMap<String, (String, usize, usize)>, diagnostics: &mut Vec<Diagnostic>) {
+    for include in include_files(&loaded.paths, filename, &config.include, diagnostics) {
+        if is_same_file(&include, filename) || loaded.sources.is_included(&include) {
+            continue;
+        }
+
+        let source = match std::fs::read_to_string(&include) {
+            Ok(source) => source,
+            Err(e) => {
+                diagnostics.push(Diagnostic::warning(filename, 0, 0, format!("cannot read include {}: {}", include, e)));
+                continue;
+            }
+        };
+
+        let document = match parse_toml_document(&include, &source, diagnostics) {
+            Some(document) => document,
+            None => continue,
+        };
+        check_included_document(&include, &document, diagnostics);
+
+        for profile in parse_profiles(&include, &source, &document, &loaded.paths, parent_lines, diagnostics) {
+            merge_profile(&mut loaded.profiles, &mut loaded.sources, &include, profile, diagnostics);
+        }
+
+        loaded.sources.included.push(IncludedFile {
+            filename: include,
+            document,
+            saved: String::new(),
+        });
+    }
+}
+
+pub fn parse_toml_config(filename: &str, source: &str) -> (Option<LoadedConfig>, Vec<Diagnostic>) {
+    let mut diagnostics = Vec::new();
+
+    let config = match parse_toml_document(filename, source, &mut diagnostics) {
+        Some(config) => config,
+        None => return (None, diagnostics),
+    };
+
+    let at = |offset: usize, severity: Severity, message: String| {
+        Diagnostic::at_offset(filename, source, offset, severity, message)
+    };
+
+    let mut loaded = LoadedConfig {
+        paths: PathContext::new(config.wallpaper_root.clone(), filename),
+        ..LoadedConfig::default()
+    };
+
+    // The main config is merged last, so its values win over every included file
+    let mut parent_lines = HashMap::new();
+    load_included_profiles(filename, &config, &mut loaded, &mut parent_lines, &mut diagnostics);
+    for profile in parse_profiles(filename, source, &config, &loaded.paths, &mut parent_lines, &mut diagnostics) {
+        merge_profile(&mut loaded.profiles, &mut loaded.sources, filename, profile, &mut diagnostics);
+    }
+
+    let mut names: Vec<&String> = loaded.profiles.keys().collect();
+    names.sort();
+    for name in names {
+        let profile = &loaded.profiles[name];
+        if profile.extends.is_some() {
+            continue;
+        }
+        for device in profile.overlays.keys().filter(|device| !profile.monitor_wallpapers.contains_key(*device)) {
+            diagnostics.push(Diagnostic::error(filename, 0, 0,
+                                               format!("monitor '{}' in profile '{}' has overlays but no path", device, name)));
+        }
+    }
+    check_profile_parents(filename, &loaded, &parent_lines, &mut diagnostics);
+
+    // Remember how each included file would be saved right now, so only edited ones are rewritten
+    let sources = loaded.sources.clone();
+    for included in &mut loaded.sources.included {
+        let profiles = profiles_for_file(&loaded.profiles, &sources, &included.filename, &included.document);
+        included.saved = serialize_profiles(&profiles);
+    }
+
+    let mut schedule_lines = Vec::new();
+    for entry in &config.schedule {
+        if let Some(message) = check_schedule_time(*entry.hour.get_ref(), *entry.minute.get_ref()) {
+            let span = if *entry.hour.get_ref() > 23 { entry.hour.span() } else { entry.minute.span() };
+            diagnostics.push(at(span.start, Severity::Error, message));
+        }
+
+        loaded.schedule.push(ScheduleEntry {
+            profile_name: entry.profile.get_ref().clone(),
+            hour: *entry.hour.get_ref(),
+            minute: *entry.minute.get_ref(),
+            enabled: entry.enabled,
+        });
+        schedule_lines.push(line_column(source, entry.profile.span().start));
+    }
+    check_schedule_profiles(filename, &loaded, &schedule_lines, &mut diagnostics);
+
+    for entry in &config.templates {
+        if !Path::new(entry.template.get_ref()).exists() {
+            diagnostics.push(at(entry.template.span().start, Severity::Warning,
+                                format!("template file not found: {}", entry.template.get_ref())));
+        }
+        loaded.theme_templates.push((entry.template.get_ref().clone(), entry.output.clone()));
+    }
+    loaded.hooks = parse_hooks(filename, source, &config.hooks, &mut diagnostics);
+
+    loaded.document = config;
+    (Some(loaded), diagnostics)
+}
+
+pub fn serialize_profiles(profiles: &BTreeMap<String, ProfileConfig>) -> String {
+    serialize_config(&ConfigFile {
+        version: CONFIG_VERSION,
+        profiles: profiles.clone(),
+        ..ConfigFile::default()
+    }).unwrap_or_default()
+}
+
+pub fn serialize_config(config: &ConfigFile) -> Result<String, String> {
+    toml::to_string_pretty(config).map_err(|e| format!("Failed to serialize config: {}", e))
+}
+
+// Compares values by what they mean, not by how they are written
+fn plain_value(value: &Value) -> Option<toml::Value> {
+    toml::from_str::<toml::Table>(&format!("v = {}", value)).ok()?.remove("v")
+}
+
+fn merge_table(previous: &mut Table, updated: &Table) {
+    let removed: Vec<String> = previous.iter()
+        .map(|(key, _)| key.to_string())
+        .filter(|key| !updated.contains_key(key))
+        .collect();
+    for key in removed {
+        previous.remove(&key);
+    }
+
+    for (key, item) in updated.iter() {
+        match (previous.get_mut(key), item) {
+            (Some(Item::Table(old)), Item::Table(new)) => merge_table(old, new),
+            (Some(Item::ArrayOfTables(old)), Item::ArrayOfTables(new)) if old.len() == new.len() => {
+                for (old, new) in old.iter_mut().zip(new.iter()) {
+                    merge_table(old, new);
+                }
+            }
+            (Some(Item::Value(old)), Item::Value(new)) if plain_value(old).is_some_and(|old| plain_value(new) == Some(old)) => {}
+            // An inline table the serializer would write as [section] is left as the user wrote it
+            (Some(Item::Value(old)), Item::Table(new))
+                if plain_value(old).is_some_and(|old| plain_value(&Value::InlineTable(new.clone().into_inline_table())) == Some(old)) => {}
+            _ => {
+                previous.insert(key, item.clone());
+            }
+        }
+    }
+}
+
+// Carries the comments and layout of `previous` over to the freshly serialized `updated`:
+// values that did not change keep their formatting and comments, changed ones are rewritten
+pub fn preserve_formatting(previous: &str, updated: &str) -> String {
+    match (previous.parse::<DocumentMut>(), updated.parse::<DocumentMut>()) {
+        (Ok(mut document), Ok(updated_document)) => {
+            merge_table(document.as_table_mut(), updated_document.as_table());
+            document.to_string()
+        }
+        _ => updated.to_string(),
+    }
+}
+
+// Parses and validates `filename` without touching any state; the config is only
+// returned when parsing got far enough to produce one
+pub fn check_config_file(filename: &str) -> (Option<LoadedConfig>, Vec<Diagnostic>) {
+    match std::fs::read_to_string(filename) {
+        Ok(source) => parse_config_source(filename, filename, &source),
+        Err(e) => (None, vec![Diagnostic::error(filename, 0, 0, format!("cannot read config: {}", e))]),
+    }
+}
+
+// `format_name` decides between the legacy and the TOML parser, `filename` is only used in diagnostics
+pub fn parse_config_source(format_name: &str, filename: &str, source: &str) -> (Option<LoadedConfig>, Vec<Diagnostic>) {
+    if is_legacy_config(format_name) {
+        let (loaded, diagnostics) = parse_legacy_config(filename, source);
+        (Some(loaded), diagnostics)
+    } else {
+        parse_toml_config(filename, source)
+    }
+}
+
+pub fn backup_path(filename: &str, index: usize) -> PathBuf {
+    PathBuf::from(format!("{}.{}", filename, index))
+}
+
+// Shifts filename.1..N-1 up by one and copies the current file to filename.1;
+// the oldest version falls off the end
+pub fn rotate_backups(filename: &str, backups: usize) -> WallpaperResult<()> {
+    if backups == 0 || !Path::new(filename).exists() {
+        return Ok(());
+    }
+
+    for index in (1..backups).rev() {
+        let from = backup_path(filename, index);
+        if from.exists() {
+            let to = backup_path(filename, index + 1);
+            std::fs::rename(&from, &to)
+                .map_err(|e| WallpaperError::io(format!("moving {} to {}", from.display(), to.display()), e))?;
+        }
+    }
+
+    let first = backup_path(filename, 1);
+    std::fs::copy(filename, &first)
+        .map(|_| ())
+        .map_err(|e| WallpaperError::io(format!("backing up {} to {}", filename, first.display()), e))
+}
+
+// Writes `content` to a temporary file next to `filename`, flushes it to disk and renames
+// it over the old file, so a crash or a full disk never leaves a half written config behind
+pub fn write_atomically(filename: &str, content: &str, backups: usize) -> WallpaperResult<()> {
+    let path = Path::new(filename);
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let file_name = path.file_name()
+        .ok_or_else(|| WallpaperError::InvalidPath(format!("Invalid config path: {}", filename)))?
+        .to_string_lossy();
+    let temp_path = directory.join(format!(".{}.tmp-{}", file_name, std::process::id()));
+
+    std::fs::create_dir_all(&directory)
+        .map_err(|e| WallpaperError::io(format!("creating {}", directory.display()), e))?;
+
+    let written = File::create(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(content.as_bytes())?;
+            file.sync_all()
+        });
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(WallpaperError::io(format!("writing {}", temp_path.display()), e));
+    }
+
+    if let Err(e) = rotate_backups(filename, backups) {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(e);
+    }
+
+    if let Err(e) = std::fs::rename(&temp_path, path) {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(WallpaperError::io(format!("replacing {}", filename), e));
+    }
+
+    // Make the rename itself durable; directories cannot be opened for syncing on Windows
+    #[cfg(unix)]
+    if let Ok(dir) = File::open(&directory) {
+        let _ = dir.sync_all();
+    }
+
+    Ok(())
+}
+
+// Validates backup `index` and writes it back as the current config; the config it
+// replaces becomes backup 1, so a restore can itself be undone
+pub fn restore_backup(filename: &str, index: usize, backups: usize) -> Result<Vec<Diagnostic>, Vec<Diagnostic>> {
+    let backup = backup_path(filename, index);
+    let backup_name = backup.to_string_lossy().to_string();
+
+    let source = std::fs::read_to_string(&backup)
+        .map_err(|e| vec![Diagnostic::error(&backup_name, 0, 0, format!("cannot read backup: {}", e))])?;
+
+    let (loaded, diagnostics) = parse_config_source(filename, &backup_name, &source);
+    if loaded.is_none() || has_errors(&diagnostics) {
+        return Err(diagnostics);
+    }
+
+    write_atomically(filename, &source, backups.max(1))
+        .map_err(|e| vec![Diagnostic::error(filename, 0, 0, e.to_string())])?;
+    Ok(diagnostics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn newer_config_versions_still_load() {
+        let (loaded, diagnostics) = parse_toml_config("config.toml", "version = 99\nfuture = true\n[profiles.a]\n");
+        let loaded = loaded.unwrap();
+        assert!(!has_errors(&diagnostics));
+        assert!(loaded.profiles.contains_key("a"));
+        assert!(loaded.document.extra.contains_key("future"));
+    }
+
+    #[test]
+    fn saving_keeps_comments_of_unchanged_values() {
+        let previous = "# my wallpapers\nversion = 1\n\n[profiles.work] # weekdays\nfit = \"fill\" # looks best\nhooks = { post_apply = [\"notify\"] }\n\n\
+                        [profiles.work.monitors.M1]\npath = \"a.png\"\n";
+        let (loaded, _) = parse_toml_config("config.toml", previous);
+        let mut document = loaded.unwrap().document;
+        document.profiles.get_mut("work").unwrap().monitors.get_mut("M1").unwrap().path = Some(unspanned("b.png".to_string()));
+
+        let saved = preserve_formatting(previous, &serialize_config(&document).unwrap());
+        assert!(saved.contains("# my wallpapers"));
+        assert!(saved.contains("# weekdays"));
+        assert!(saved.contains("fit = \"fill\" # looks best"));
+        assert!(saved.contains("hooks = { post_apply = [\"notify\"] }"));
+        assert!(saved.contains("path = \"b.png\""));
+        assert!(!saved.contains("a.png"));
+    }
+}
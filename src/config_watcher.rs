@@ -0,0 +1,237 @@
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+use std::time::Duration;
+use notify_debouncer_mini::notify::{RecommendedWatcher, RecursiveMode};
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult, Debouncer};
+use log::warn;
+
+// Editors and dotfile tools often write a file several times in a row, wait until it is quiet
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+// A config file, or a directory whose *.toml files all count (profiles.d); either may not exist yet
+#[derive(Debug, Clone, PartialEq)]
+pub enum WatchPath {
+    File(PathBuf),
+    Directory(PathBuf),
+}
+
+// Watches directories instead of the files themselves: atomic saves replace a file with a
+// rename, after which a watch on the old inode would never fire again. A directory that does
+// not exist yet is watched through its nearest existing ancestor until it is created
+pub struct ConfigWatcher {
+    debouncer: Debouncer<RecommendedWatcher>,
+    receiver: Receiver<DebounceEventResult>,
+    targets: Vec<(PathBuf, Option<OsString>)>, // directory and file name, None for any *.toml in it
+    watched: HashSet<PathBuf>,
+}
+
+impl ConfigWatcher {
+    pub fn new(paths: &[WatchPath]) -> Result<Self, String> {
+        let (sender, receiver) = channel();
+        let debouncer = new_debouncer(DEBOUNCE, sender)
+            .map_err(|e| format!("Failed to create file watcher: {}", e))?;
+
+        let mut watcher = Self {
+            debouncer,
+            receiver,
+            targets: Vec::new(),
+            watched: HashSet::new(),
+        };
+        watcher.update(paths)?;
+        Ok(watcher)
+    }
+
+    // Called after every reload, since includes can be added or removed
+    pub fn update(&mut self, paths: &[WatchPath]) -> Result<(), String> {
+        let mut targets = Vec::new();
+        for path in paths {
+            match path {
+                WatchPath::Directory(directory) => targets.push((absolute(directory), None)),
+                WatchPath::File(file) => {
+                    let file_name = file.file_name()
+                        .ok_or_else(|| format!("Invalid config path: {}", file.display()))?
+                        .to_os_string();
+                    let directory = match file.parent() {
+                        Some(parent) if !parent.as_os_str().is_empty() => parent,
+                        _ => Path::new("."),
+                    };
+                    targets.push((absolute(directory), Some(file_name)));
+                }
+            }
+        }
+
+        self.targets = targets;
+        self.rewatch()
+    }
+
+    fn rewatch(&mut self) -> Result<(), String> {
+        let wanted: HashSet<PathBuf> = self.targets.iter()
+            .map(|(directory, _)| existing_ancestor(directory))
+            .collect();
+
+        for directory in self.watched.difference(&wanted) {
+            // Fails when the directory was removed, its watch is gone with it
+            let _ = self.debouncer.watcher().unwatch(directory);
+        }
+        for directory in wanted.difference(&self.watched) {
+            self.debouncer.watcher()
+                .watch(directory, RecursiveMode::NonRecursive)
+                .map_err(|e| format!("Failed to watch {}: {}", directory.display(), e))?;
+        }
+
+        self.watched = wanted;
+        Ok(())
+    }
+
+    // The watches on a removed directory are dropped by the OS, so start over
+    fn rewatch_all(&mut self) {
+        for directory in self.watched.drain() {
+            let _ = self.debouncer.watcher().unwatch(&directory);
+        }
+        if let Err(e) = self.rewatch() {
+            warn!("Config watcher error: {}", e);
+        }
+    }
+
+    fn is_target(&self, path: &Path) -> bool {
+        self.targets.iter().any(|(directory, file_name)| {
+            path.parent() == Some(directory.as_path()) && match file_name {
+                Some(file_name) => path.file_name() == Some(file_name.as_os_str()),
+                None => path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("toml")),
+            }
+        })
+    }
+
+    // A watched directory, or one on the way to it, was created, removed or renamed
+    fn is_on_target_path(&self, path: &Path) -> bool {
+        self.targets.iter().any(|(directory, _)| directory.starts_with(path))
+    }
+
+    // Non-blocking, true if any watched config file changed since the last call
+    pub fn changed(&mut self) -> bool {
+        let mut changed = false;
+        let mut moved = false;
+        while let Ok(result) = self.receiver.try_recv() {
+            match result {
+                Ok(events) => for event in &events {
+                    let on_target_path = self.is_on_target_path(&event.path);
+                    moved |= on_target_path;
+                    changed |= on_target_path || self.is_target(&event.path);
+                },
+                Err(e) => warn!("Config watcher error: {}", e),
+            }
+        }
+
+        if moved {
+            self.rewatch_all();
+        }
+        changed
+    }
+}
+
+// Events are reported below the watched path, so both sides have to be spelled the same way.
+// Missing directories keep their name below the canonical form of the part that exists
+fn absolute(path: &Path) -> PathBuf {
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    for ancestor in path.ancestors() {
+        if let Ok(canonical) = ancestor.canonicalize() {
+            return match path.strip_prefix(ancestor) {
+                Ok(rest) if !rest.as_os_str().is_empty() => canonical.join(rest),
+                _ => canonical,
+            };
+        }
+    }
+    path
+}
+
+fn existing_ancestor(directory: &Path) -> PathBuf {
+    directory.ancestors()
+        .find(|ancestor| ancestor.is_dir())
+        .unwrap_or(directory)
+        .to_path_buf()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::Instant;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("wallpaper-helper-watcher-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // Waits out the debounce, a change is reported once the writes have been quiet for a while
+    fn wait_for_change(watcher: &mut ConfigWatcher) -> bool {
+        let started = Instant::now();
+        while started.elapsed() < DEBOUNCE * 10 {
+            if watcher.changed() {
+                return true;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        false
+    }
+
+    fn settle(watcher: &mut ConfigWatcher) -> bool {
+        std::thread::sleep(DEBOUNCE * 3);
+        watcher.changed()
+    }
+
+    #[test]
+    fn directories_that_do_not_exist_yet_are_picked_up() {
+        let dir = test_dir("missing");
+        let config_dir = dir.join("config");
+        let config = config_dir.join("config.toml");
+        let drop_ins = config_dir.join("profiles.d");
+        let mut watcher = ConfigWatcher::new(&[WatchPath::File(config.clone()), WatchPath::Directory(drop_ins.clone())]).unwrap();
+
+        fs::create_dir_all(&config_dir).unwrap();
+        fs::write(&config, "version = 2\n").unwrap();
+        assert!(wait_for_change(&mut watcher));
+        assert!(!settle(&mut watcher));
+
+        fs::create_dir_all(&drop_ins).unwrap();
+        assert!(wait_for_change(&mut watcher));
+        fs::write(drop_ins.join("work.toml"), "").unwrap();
+        assert!(wait_for_change(&mut watcher));
+
+        // Only *.toml files count in a drop-in directory, and only the config next to it
+        fs::write(drop_ins.join("notes.txt"), "").unwrap();
+        fs::write(config_dir.join("other.toml"), "").unwrap();
+        assert!(!settle(&mut watcher));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn updates_follow_includes_added_after_a_reload() {
+        let dir = test_dir("includes");
+        let config = dir.join("config.toml");
+        let include = dir.join("shared").join("screens.toml");
+        fs::create_dir_all(include.parent().unwrap()).unwrap();
+        fs::write(&config, "").unwrap();
+        let mut watcher = ConfigWatcher::new(&[WatchPath::File(config.clone())]).unwrap();
+
+        fs::write(&include, "").unwrap();
+        assert!(!settle(&mut watcher));
+
+        watcher.update(&[WatchPath::File(config.clone()), WatchPath::File(include.clone())]).unwrap();
+        fs::write(&include, "[[profiles]]\n").unwrap();
+        assert!(wait_for_change(&mut watcher));
+
+        // Dropping the include stops the watch on its directory
+        watcher.update(&[WatchPath::File(config.clone())]).unwrap();
+        fs::write(&include, "").unwrap();
+        assert!(!settle(&mut watcher));
+        fs::write(&config, "version = 2\n").unwrap();
+        assert!(wait_for_change(&mut watcher));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,652 @@
+use std::io::{self, BufRead, BufReader, Write};
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+use interprocess::local_socket::prelude::*;
+use interprocess::local_socket::{ListenerOptions, Name, Stream};
+#[cfg(unix)]
+use interprocess::local_socket::GenericFilePath;
+#[cfg(not(unix))]
+use interprocess::local_socket::GenericNamespaced;
+use log::{debug, error, info, warn};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use crate::apply_report::ApplyReport;
+use crate::config_watcher::ConfigWatcher;
+use crate::dbus::DbusService;
+use crate::error::{WallpaperError, WallpaperResult};
+use crate::output::DaemonStatusRecord;
+use crate::wallpaper_manager::WallpaperManager;
+
+// JSON-RPC 2.0 error codes, everything the manager reports uses APPLICATION_ERROR
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+const APPLICATION_ERROR: i32 = -32000;
+
+// How often the daemon looks at the schedule and the config watcher while no client calls
+const TICK: Duration = Duration::from_secs(1);
+const MONITOR_POLL: Duration = Duration::from_secs(10);
+
+// One request or response per line, e.g.
+// {"jsonrpc": "2.0", "id": 1, "method": "apply", "params": {"profile": "work"}}
+#[derive(Debug, Serialize, Deserialize)]
+struct RpcRequest {
+    jsonrpc: String,
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RpcResponse {
+    jsonrpc: String,
+    id: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RpcError {
+    code: i32,
+    message: String,
+}
+
+impl RpcResponse {
+    fn result(id: Value, result: Value) -> Self {
+        Self {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result: Some(result),
+            error: None,
+        }
+    }
+
+    fn error(id: Value, code: i32, message: impl Into<String>) -> Self {
+        Self {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result: None,
+            error: Some(RpcError { code, message: message.into() }),
+        }
+    }
+}
+
+// A request from a client thread and where its response goes
+struct Call {
+    request: RpcRequest,
+    reply: Sender<RpcResponse>,
+}
+
+// Things that happened in the daemon, sent out as D-Bus signals once the call that caused them has returned
+#[derive(Debug, Clone)]
+pub enum DaemonEvent {
+    ProfileApplied { profile: String, success: bool },
+    MonitorsChanged(Vec<String>),
+}
+
+fn into_result<T: DeserializeOwned>(response: RpcResponse) -> WallpaperResult<T> {
+    if let Some(error) = response.error {
+        return Err(WallpaperError::Daemon(error.message));
+    }
+    serde_json::from_value(response.result.unwrap_or(Value::Null)).map_err(|e| WallpaperError::Daemon(e.to_string()))
+}
+
+// Lets front ends inside the daemon process, like the D-Bus service, call it without a socket
+#[derive(Clone)]
+pub struct DaemonHandle {
+    calls: Sender<Call>,
+}
+
+impl DaemonHandle {
+    pub fn call<T: DeserializeOwned>(&self, method: &str, params: Value) -> WallpaperResult<T> {
+        let (reply, response) = channel();
+        let request = RpcRequest {
+            jsonrpc: "2.0".to_string(),
+            id: Value::Null,
+            method: method.to_string(),
+            params,
+        };
+
+        let stopped = || WallpaperError::Daemon("The daemon has stopped".to_string());
+        self.calls.send(Call { request, reply }).map_err(|_| stopped())?;
+        into_result(response.recv().map_err(|_| stopped())?)
+    }
+
+    // Answers every call with `respond(method, params)` instead of a manager
+    #[cfg(test)]
+    pub fn responding(respond: impl Fn(&str, Value) -> Value + Send + 'static) -> Self {
+        let (calls, received) = channel::<Call>();
+        thread::spawn(move || {
+            for call in received {
+                let result = respond(&call.request.method, call.request.params);
+                let _ = call.reply.send(RpcResponse::result(call.request.id, result));
+            }
+        });
+        Self { calls }
+    }
+}
+
+// One daemon per user. On Unix the socket is a file in a directory only the user can enter, abstract
+// sockets have no permissions at all; on Windows it is a named pipe, which only its creator may write to
+#[cfg(unix)]
+fn socket_name() -> io::Result<Name<'static>> {
+    socket_dir()?.join("wallpaper-helper.sock").to_fs_name::<GenericFilePath>()
+}
+
+#[cfg(not(unix))]
+fn socket_name() -> io::Result<Name<'static>> {
+    let user = std::env::var("USERNAME").or_else(|_| std::env::var("USER")).unwrap_or_default();
+    format!("wallpaper-helper-{}.sock", user).to_ns_name::<GenericNamespaced>()
+}
+
+// $XDG_RUNTIME_DIR is private to its user, without one a private directory in the temp dir is used
+// and refused if someone else created it first
+#[cfg(unix)]
+fn socket_dir() -> io::Result<PathBuf> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+    if let Some(runtime_dir) = std::env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()) {
+        return Ok(PathBuf::from(runtime_dir));
+    }
+
+    let uid = unsafe { libc::geteuid() };
+    let dir = std::env::temp_dir().join(format!("wallpaper-helper-{}", uid));
+    match std::fs::DirBuilder::new().mode(0o700).create(&dir) {
+        Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e),
+        _ => {}
+    }
+
+    let metadata = std::fs::symlink_metadata(&dir)?;
+    if !metadata.is_dir() || metadata.uid() != uid || metadata.mode() & 0o077 != 0 {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied,
+                                  format!("{} is not a private directory", dir.display())));
+    }
+    Ok(dir)
+}
+
+// The socket's directory already keeps other users out, this also covers a runtime dir with loose permissions
+#[cfg(unix)]
+fn is_same_user(stream: &Stream) -> bool {
+    stream.peer_creds().ok().and_then(|creds| creds.euid()) == Some(unsafe { libc::geteuid() })
+}
+
+#[cfg(not(unix))]
+fn is_same_user(_stream: &Stream) -> bool {
+    true
+}
+
+// A socket file left behind by a daemon that crashed is replaced, the one of a running daemon is not
+fn listen() -> io::Result<LocalSocketListener> {
+    let listen = |overwrite| ListenerOptions::new().name(socket_name()?).try_overwrite(overwrite).create_sync();
+    match listen(false) {
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse && DaemonClient::connect().is_none() => listen(true),
+        result => result,
+    }
+}
+
+fn to_value<T: Serialize>(value: T) -> WallpaperResult<Value> {
+    serde_json::to_value(value).map_err(|e| WallpaperError::Daemon(e.to_string()))
+}
+
+struct Daemon {
+    manager: WallpaperManager,
+    config_file: String,
+    current_profile: Option<String>, // last one applied, by a client or the schedule
+    started: Instant,
+    monitors_checked: Instant,
+    events: Vec<DaemonEvent>,
+    watcher: Option<ConfigWatcher>,
+}
+
+impl Daemon {
+    fn applied(&mut self, report: &ApplyReport) {
+        if report.error.is_none() {
+            self.current_profile = Some(report.profile.clone());
+        }
+        self.events.push(DaemonEvent::ProfileApplied {
+            profile: report.profile.clone(),
+            success: report.success(),
+        });
+    }
+
+    fn apply(&mut self, profile_name: &str) -> ApplyReport {
+        let report = self.manager.apply_profile(profile_name);
+        self.applied(&report);
+        report
+    }
+
+    // Profiles are cycled in name order, wrapping around at either end
+    fn switch_profile(&mut self, forward: bool) -> WallpaperResult<ApplyReport> {
+        let names: Vec<String> = self.manager.list_profiles().into_iter().map(|profile| profile.name).collect();
+        if names.is_empty() {
+            return Err(WallpaperError::Daemon("There are no profiles to switch to".to_string()));
+        }
+
+        let index = match self.current_profile.as_ref().and_then(|current| names.iter().position(|name| name == current)) {
+            Some(index) if forward => (index + 1) % names.len(),
+            Some(index) => (index + names.len() - 1) % names.len(),
+            None if forward => 0,
+            None => names.len() - 1,
+        };
+        Ok(self.apply(&names[index]))
+    }
+
+    fn status(&self) -> DaemonStatusRecord {
+        DaemonStatusRecord {
+            pid: std::process::id(),
+            config: self.config_file.clone(),
+            profile: self.current_profile.clone(),
+            profiles: self.manager.profiles.len(),
+            monitors: self.manager.monitors.len(),
+            scheduler_running: self.manager.scheduler_running.load(Ordering::Relaxed),
+            schedule: self.manager.list_schedule(),
+            pending_retries: self.manager.pending_retries.len(),
+            uptime_secs: self.started.elapsed().as_secs(),
+        }
+    }
+
+    fn handle(&mut self, request: RpcRequest) -> RpcResponse {
+        debug!("Daemon call: {} {}", request.method, request.params);
+
+        let result = match request.method.as_str() {
+            "apply" => match request.params.get("profile").and_then(Value::as_str) {
+                Some(profile_name) => to_value(self.apply(profile_name)),
+                None => return RpcResponse::error(request.id, INVALID_PARAMS, "apply needs a \"profile\" parameter"),
+            },
+            "next" => self.switch_profile(true).and_then(to_value),
+            "previous" => self.switch_profile(false).and_then(to_value),
+            "list" => to_value(self.manager.list_profiles()),
+            "reload" => self.reload().map(|_| Value::Null),
+            "status" => to_value(self.status()),
+            method => return RpcResponse::error(request.id, METHOD_NOT_FOUND, format!("Unknown method '{}'", method)),
+        };
+
+        match result {
+            Ok(value) => RpcResponse::result(request.id, value),
+            Err(e) => RpcResponse::error(request.id, APPLICATION_ERROR, e.to_string()),
+        }
+    }
+
+    // Includes may come and go with the reload, the watcher follows them
+    fn reload(&mut self) -> WallpaperResult<()> {
+        let result = self.manager.reload_config(&self.config_file);
+        if let Some(watcher) = &mut self.watcher {
+            if let Err(e) = watcher.update(&self.manager.config_watch_paths(&self.config_file)) {
+                warn!("Not watching all of {} for changes: {}", self.config_file, e);
+            }
+        }
+        result
+    }
+
+    // The scheduler thread only marks profiles as due, they are applied here between calls
+    fn tick(&mut self) {
+        if self.watcher.as_mut().is_some_and(|watcher| watcher.changed()) {
+            if let Err(e) = self.reload() {
+                error!("Reload failed, still using the previous configuration: {}", e);
+            }
+        }
+
+        for report in self.manager.apply_due_profiles() {
+            info!("{}", report.summary());
+            self.applied(&report);
+        }
+
+        // Plugging in or removing a monitor is only noticed by looking again
+        if self.monitors_checked.elapsed() >= MONITOR_POLL {
+            self.monitors_checked = Instant::now();
+            let device_names = |manager: &WallpaperManager| -> Vec<String> {
+                manager.monitors.iter().map(|monitor| monitor.device_name.clone()).collect()
+            };
+
+            let before = device_names(&self.manager);
+            self.manager.refresh_monitors();
+            let after = device_names(&self.manager);
+            if before != after {
+                info!("Monitors changed: {}", after.join(", "));
+                self.events.push(DaemonEvent::MonitorsChanged(after));
+            }
+        }
+    }
+}
+
+// Answers one line of a client. Requests without an id are notifications: they are carried out
+// but get no response, unless they are too broken to tell what they were meant to be
+fn respond(line: &str, calls: &Sender<Call>) -> WallpaperResult<Option<RpcResponse>> {
+    let value = match serde_json::from_str::<Value>(line) {
+        Ok(value) => value,
+        Err(e) => return Ok(Some(RpcResponse::error(Value::Null, PARSE_ERROR, e.to_string()))),
+    };
+    let notification = value.get("id").is_none();
+    let request = match serde_json::from_value::<RpcRequest>(value) {
+        Ok(request) if request.jsonrpc == "2.0" => request,
+        Ok(request) => return Ok(Some(RpcResponse::error(request.id, INVALID_REQUEST,
+                                                         format!("Unsupported jsonrpc version '{}'", request.jsonrpc)))),
+        Err(e) => return Ok(Some(RpcResponse::error(Value::Null, INVALID_REQUEST, e.to_string()))),
+    };
+
+    let stopped = || WallpaperError::Daemon("The daemon has stopped".to_string());
+    let (reply, response) = channel();
+    calls.send(Call { request, reply }).map_err(|_| stopped())?;
+    let response = response.recv().map_err(|_| stopped())?;
+    Ok((!notification).then_some(response))
+}
+
+// Reads requests line by line until the client hangs up; the manager itself stays on the daemon thread
+fn serve_client(stream: Stream, calls: Sender<Call>) {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        match reader.read_line(&mut line) {
+            Ok(0) => return,
+            Ok(_) if line.trim().is_empty() => continue,
+            Ok(_) => {}
+            Err(e) => {
+                debug!("Daemon client left: {}", e);
+                return;
+            }
+        }
+
+        let response = match respond(&line, &calls) {
+            Ok(Some(response)) => response,
+            Ok(None) => continue,
+            Err(_) => return,
+        };
+
+        let Ok(mut json) = serde_json::to_string(&response) else { return };
+        json.push('\n');
+        if reader.get_mut().write_all(json.as_bytes()).is_err() {
+            return;
+        }
+    }
+}
+
+// Owns the manager and the scheduler until the process is stopped; `dbus_address` replaces the
+// session bus, e.g. with a private dbus-daemon
+pub fn run_daemon(mut manager: WallpaperManager, config_file: &str, dbus_address: Option<&str>) -> WallpaperResult<()> {
+    let listener = listen().map_err(|e| match e.kind() {
+        io::ErrorKind::AddrInUse => WallpaperError::Daemon("Another daemon is already running".to_string()),
+        _ => WallpaperError::io("creating the daemon socket", e),
+    })?;
+
+    let (calls, received) = channel::<Call>();
+    let handle = DaemonHandle { calls: calls.clone() };
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) if !is_same_user(&stream) => warn!("Refused a daemon connection from another user"),
+                Ok(stream) => {
+                    let calls = calls.clone();
+                    thread::spawn(move || serve_client(stream, calls));
+                }
+                Err(e) => warn!("Daemon connection failed: {}", e),
+            }
+        }
+    });
+
+    let watcher = match ConfigWatcher::new(&manager.config_watch_paths(config_file)) {
+        Ok(watcher) => Some(watcher),
+        Err(e) => {
+            warn!("Not watching {} for changes: {}", config_file, e);
+            None
+        }
+    };
+
+    // Without a session bus, e.g. on Windows, the socket is still there
+    let dbus = match DbusService::start(handle, dbus_address) {
+        Ok(dbus) => Some(dbus),
+        Err(e) => {
+            warn!("Not registering on D-Bus: {}", e);
+            None
+        }
+    };
+
+    manager.start_scheduler();
+    let mut daemon = Daemon {
+        manager,
+        config_file: config_file.to_string(),
+        current_profile: None,
+        started: Instant::now(),
+        monitors_checked: Instant::now(),
+        events: Vec::new(),
+        watcher,
+    };
+    info!("Daemon started for {}", config_file);
+
+    loop {
+        match received.recv_timeout(TICK) {
+            Ok(call) => {
+                let response = daemon.handle(call.request);
+                let _ = call.reply.send(response);
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Err(WallpaperError::Daemon("The daemon socket was closed".to_string())),
+        }
+        daemon.tick();
+
+        for event in daemon.events.drain(..) {
+            if let Some(dbus) = &dbus {
+                dbus.emit(&event);
+            }
+        }
+    }
+}
+
+// The command line and the window go through this when a daemon is running
+pub struct DaemonClient {
+    stream: BufReader<Stream>,
+    next_id: u64,
+}
+
+impl DaemonClient {
+    // None when no daemon is listening
+    pub fn connect() -> Option<Self> {
+        let stream = Stream::connect(socket_name().ok()?).ok()?;
+        Some(Self {
+            stream: BufReader::new(stream),
+            next_id: 1,
+        })
+    }
+
+    fn call<T: DeserializeOwned>(&mut self, method: &str, params: Value) -> WallpaperResult<T> {
+        let request = RpcRequest {
+            jsonrpc: "2.0".to_string(),
+            id: json!(self.next_id),
+            method: method.to_string(),
+            params,
+        };
+        self.next_id += 1;
+
+        let io_error = |e| WallpaperError::io("talking to the daemon", e);
+        let mut line = serde_json::to_string(&request).map_err(|e| WallpaperError::Daemon(e.to_string()))?;
+        line.push('\n');
+        self.stream.get_mut().write_all(line.as_bytes()).map_err(io_error)?;
+
+        line.clear();
+        if self.stream.read_line(&mut line).map_err(io_error)? == 0 {
+            return Err(WallpaperError::Daemon("The daemon closed the connection".to_string()));
+        }
+
+        let response: RpcResponse = serde_json::from_str(&line).map_err(|e| WallpaperError::Daemon(e.to_string()))?;
+        into_result(response)
+    }
+
+    pub fn apply(&mut self, profile_name: &str) -> WallpaperResult<ApplyReport> {
+        self.call("apply", json!({ "profile": profile_name }))
+    }
+
+    pub fn next_profile(&mut self) -> WallpaperResult<ApplyReport> {
+        self.call("next", Value::Null)
+    }
+
+    pub fn reload(&mut self) -> WallpaperResult<()> {
+        self.call("reload", Value::Null)
+    }
+
+    pub fn status(&mut self) -> WallpaperResult<DaemonStatusRecord> {
+        self.call("status", Value::Null)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::Receiver;
+    use interprocess::local_socket::{GenericFilePath, GenericNamespaced};
+    use crate::wallpaper_manager::WallpaperProfile;
+    use super::*;
+
+    // Answers every call with its method and reports which methods were called
+    fn echo() -> (Sender<Call>, Receiver<String>) {
+        let (calls, received) = channel::<Call>();
+        let (called, methods) = channel();
+        thread::spawn(move || {
+            for call in received {
+                let _ = called.send(call.request.method.clone());
+                let _ = call.reply.send(RpcResponse::result(call.request.id, json!(call.request.method)));
+            }
+        });
+        (calls, methods)
+    }
+
+    fn error_code(response: &RpcResponse) -> Option<i32> {
+        response.error.as_ref().map(|error| error.code)
+    }
+
+    #[test]
+    fn broken_requests_get_error_responses() {
+        let (calls, methods) = echo();
+        let respond = |line: &str| respond(line, &calls).unwrap().unwrap();
+
+        let response = respond("{\"jsonrpc\": \"2.0\", \"id\": 1,");
+        assert_eq!((error_code(&response), response.id), (Some(PARSE_ERROR), Value::Null));
+
+        let response = respond(r#"{"jsonrpc": "2.0", "id": 2}"#);
+        assert_eq!(error_code(&response), Some(INVALID_REQUEST));
+
+        let response = respond(r#"{"jsonrpc": "1.0", "id": 3, "method": "list"}"#);
+        assert_eq!((error_code(&response), response.id), (Some(INVALID_REQUEST), json!(3)));
+
+        // Without an id this is still no valid notification, so it is answered
+        let response = respond(r#"{"method": "list"}"#);
+        assert_eq!(error_code(&response), Some(INVALID_REQUEST));
+
+        assert!(methods.try_recv().is_err());
+    }
+
+    #[test]
+    fn notifications_are_carried_out_without_a_response() {
+        let (calls, methods) = echo();
+
+        assert!(respond(r#"{"jsonrpc": "2.0", "method": "next"}"#, &calls).unwrap().is_none());
+        assert_eq!(methods.recv().unwrap(), "next");
+
+        let response = respond(r#"{"jsonrpc": "2.0", "id": "a", "method": "list"}"#, &calls).unwrap().unwrap();
+        assert_eq!((response.id, response.result), (json!("a"), Some(json!("list"))));
+
+        // A null id is an id, not a notification
+        let response = respond(r#"{"jsonrpc": "2.0", "id": null, "method": "status"}"#, &calls).unwrap();
+        assert!(response.is_some());
+    }
+
+    #[test]
+    fn clients_get_one_response_line_per_request() {
+        let name = format!("wallpaper-helper-test-{}.sock", std::process::id());
+        let name = if GenericNamespaced::is_supported() {
+            name.to_ns_name::<GenericNamespaced>().unwrap()
+        } else {
+            std::env::temp_dir().join(name).to_fs_name::<GenericFilePath>().unwrap()
+        };
+        let listener = ListenerOptions::new().name(name.clone()).create_sync().unwrap();
+        let (calls, _methods) = echo();
+        let server = thread::spawn(move || serve_client(listener.accept().unwrap(), calls));
+
+        let mut client = BufReader::new(Stream::connect(name).unwrap());
+        // Blank lines and notifications get nothing back, a request may arrive in pieces
+        client.get_mut().write_all(concat!(
+            "\n",
+            r#"{"jsonrpc": "2.0", "id": 1, "method": "list"}"#, "\n",
+            r#"{"jsonrpc": "2.0", "method": "next"}"#, "\n",
+            r#"{"jsonrpc": "2.0", "id": 2, "#,
+        ).as_bytes()).unwrap();
+        client.get_mut().flush().unwrap();
+        thread::sleep(Duration::from_millis(50));
+        client.get_mut().write_all(b"\"method\": \"status\"}\nnot json\n").unwrap();
+
+        let mut responses = Vec::new();
+        for _ in 0..3 {
+            let mut line = String::new();
+            client.read_line(&mut line).unwrap();
+            assert!(line.ends_with('\n'));
+            responses.push(serde_json::from_str::<RpcResponse>(&line).unwrap());
+        }
+        let ids: Vec<&Value> = responses.iter().map(|response| &response.id).collect();
+        assert_eq!(ids, [&json!(1), &json!(2), &Value::Null]);
+        assert_eq!(error_code(&responses[2]), Some(PARSE_ERROR));
+
+        drop(client);
+        server.join().unwrap();
+    }
+
+    fn daemon(profiles: &[&str]) -> Daemon {
+        let mut manager = WallpaperManager::new();
+        for name in profiles {
+            manager.profiles.insert(name.to_string(), WallpaperProfile::new(name));
+        }
+        Daemon {
+            manager,
+            config_file: "config.toml".to_string(),
+            current_profile: None,
+            started: Instant::now(),
+            monitors_checked: Instant::now(),
+            events: Vec::new(),
+            watcher: None,
+        }
+    }
+
+    fn call(daemon: &mut Daemon, method: &str, params: Value) -> RpcResponse {
+        daemon.handle(RpcRequest {
+            jsonrpc: "2.0".to_string(),
+            id: json!(1),
+            method: method.to_string(),
+            params,
+        })
+    }
+
+    #[test]
+    fn the_dispatcher_checks_methods_and_params() {
+        let mut daemon = daemon(&[]);
+        assert_eq!(error_code(&call(&mut daemon, "shutdown", Value::Null)), Some(METHOD_NOT_FOUND));
+        assert_eq!(error_code(&call(&mut daemon, "apply", json!({ "name": "work" }))), Some(INVALID_PARAMS));
+        assert_eq!(error_code(&call(&mut daemon, "next", Value::Null)), Some(APPLICATION_ERROR));
+        assert_eq!(call(&mut daemon, "list", Value::Null).result, Some(json!([])));
+    }
+
+    #[test]
+    fn next_and_previous_cycle_through_the_profiles() {
+        let mut daemon = daemon(&["b", "a"]);
+        let applied = |response: RpcResponse| into_result::<ApplyReport>(response).unwrap().profile;
+
+        assert_eq!(applied(call(&mut daemon, "next", Value::Null)), "a");
+        assert_eq!(applied(call(&mut daemon, "next", Value::Null)), "b");
+        assert_eq!(applied(call(&mut daemon, "next", Value::Null)), "a");
+        assert_eq!(applied(call(&mut daemon, "previous", Value::Null)), "b");
+        assert_eq!(daemon.events.len(), 4);
+
+        // A failed apply is reported but does not move the current profile
+        let report: ApplyReport = into_result(call(&mut daemon, "apply", json!({ "profile": "missing" }))).unwrap();
+        assert!(report.error.is_some());
+        let status: DaemonStatusRecord = into_result(call(&mut daemon, "status", Value::Null)).unwrap();
+        assert_eq!((status.profile.as_deref(), status.profiles), (Some("b"), 2));
+    }
+}
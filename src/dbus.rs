@@ -0,0 +1,145 @@
+use log::warn;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use zbus::blocking::connection::Builder;
+use zbus::blocking::Connection;
+use zbus::object_server::SignalEmitter;
+use zbus::{fdo, interface};
+use crate::apply_report::ApplyReport;
+use crate::daemon::{DaemonEvent, DaemonHandle};
+use crate::error::WallpaperError;
+use crate::output::ProfileRecord;
+
+pub const BUS_NAME: &str = "org.wallpaperhelper.Manager";
+pub const OBJECT_PATH: &str = "/org/wallpaperhelper/Manager";
+
+fn failed(e: WallpaperError) -> fdo::Error {
+    fdo::Error::Failed(e.to_string())
+}
+
+// Forwards every call to the daemon thread, which owns the manager
+struct ManagerInterface {
+    daemon: DaemonHandle,
+}
+
+impl ManagerInterface {
+    // Applying can take a while, so the daemon is waited for on a blocking task and the bus keeps being served
+    async fn call<T: DeserializeOwned + Send + 'static>(&self, method: &'static str, params: Value) -> fdo::Result<T> {
+        let daemon = self.daemon.clone();
+        blocking::unblock(move || daemon.call(method, params)).await.map_err(failed)
+    }
+
+    // Whether every monitor was set, and the one line summary the window shows
+    async fn apply_report(&self, method: &'static str, params: Value) -> fdo::Result<(bool, String)> {
+        let report: ApplyReport = self.call(method, params).await?;
+        Ok((report.success(), report.summary()))
+    }
+}
+
+#[interface(name = "org.wallpaperhelper.Manager")]
+impl ManagerInterface {
+    #[zbus(out_args("success", "summary"))]
+    async fn apply_profile(&self, name: &str) -> fdo::Result<(bool, String)> {
+        self.apply_report("apply", json!({ "profile": name })).await
+    }
+
+    #[zbus(out_args("profiles"))]
+    async fn list_profiles(&self) -> fdo::Result<Vec<String>> {
+        let profiles: Vec<ProfileRecord> = self.call("list", Value::Null).await?;
+        Ok(profiles.into_iter().map(|profile| profile.name).collect())
+    }
+
+    #[zbus(out_args("success", "summary"))]
+    async fn next(&self) -> fdo::Result<(bool, String)> {
+        self.apply_report("next", Value::Null).await
+    }
+
+    #[zbus(out_args("success", "summary"))]
+    async fn previous(&self) -> fdo::Result<(bool, String)> {
+        self.apply_report("previous", Value::Null).await
+    }
+
+    #[zbus(signal)]
+    async fn profile_applied(emitter: &SignalEmitter<'_>, profile: &str, success: bool) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn monitors_changed(emitter: &SignalEmitter<'_>, monitors: Vec<String>) -> zbus::Result<()>;
+}
+
+pub struct DbusService {
+    connection: Connection,
+}
+
+impl DbusService {
+    // `address` None means the session bus
+    pub fn start(daemon: DaemonHandle, address: Option<&str>) -> zbus::Result<Self> {
+        let builder = match address {
+            Some(address) => Builder::address(address)?,
+            None => Builder::session()?,
+        };
+        let connection = builder
+            .name(BUS_NAME)?
+            .serve_at(OBJECT_PATH, ManagerInterface { daemon })?
+            .build()?;
+        Ok(Self { connection })
+    }
+
+    pub fn emit(&self, event: &DaemonEvent) {
+        let result = SignalEmitter::new(self.connection.inner(), OBJECT_PATH).and_then(|emitter| match event {
+            DaemonEvent::ProfileApplied { profile, success } => {
+                zbus::block_on(ManagerInterface::profile_applied(&emitter, profile, *success))
+            }
+            DaemonEvent::MonitorsChanged(monitors) => {
+                zbus::block_on(ManagerInterface::monitors_changed(&emitter, monitors.clone()))
+            }
+        });
+
+        if let Err(e) = result {
+            warn!("Failed to send the D-Bus signal for {:?}: {}", event, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader};
+    use std::process::{Command, Stdio};
+    use zbus::blocking::Proxy;
+    use super::*;
+
+    #[test]
+    #[ignore = "needs a dbus-daemon binary, run with --ignored where one is installed"]
+    fn serves_the_daemon_on_a_private_bus() {
+        let mut bus = Command::new("dbus-daemon")
+            .args(["--session", "--print-address", "--nofork"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Failed to start dbus-daemon");
+        let mut address = String::new();
+        BufReader::new(bus.stdout.take().unwrap()).read_line(&mut address).unwrap();
+        let address = address.trim();
+
+        let daemon = DaemonHandle::responding(|method, params| match method {
+            "list" => json!([{ "name": "work", "extends": null, "fit": null, "wallpapers": {}, "overlays": {} }]),
+            "apply" => serde_json::to_value(ApplyReport::new(params["profile"].as_str().unwrap())).unwrap(),
+            _ => Value::Null,
+        });
+        let service = DbusService::start(daemon, Some(address)).unwrap();
+
+        let connection = Builder::address(address).unwrap().build().unwrap();
+        let proxy = Proxy::new(&connection, BUS_NAME, OBJECT_PATH, BUS_NAME).unwrap();
+        let profiles: Vec<String> = proxy.call("ListProfiles", &()).unwrap();
+        assert_eq!(profiles, ["work"]);
+        let (success, summary): (bool, String) = proxy.call("ApplyProfile", &("work",)).unwrap();
+        assert!(success);
+        assert_eq!(summary, "Profile 'work': 0 of 0 monitors set");
+
+        let mut signals = proxy.receive_signal("ProfileApplied").unwrap();
+        service.emit(&DaemonEvent::ProfileApplied { profile: "work".to_string(), success: true });
+        let (profile, success): (String, bool) = signals.next().unwrap().body().deserialize().unwrap();
+        assert_eq!((profile.as_str(), success), ("work", true));
+
+        bus.kill().unwrap();
+        bus.wait().unwrap();
+    }
+}
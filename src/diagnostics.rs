@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl Severity {
+    fn to_string(self) -> &'static str {
+        match self {
+            Self::Warning => "warning",
+            Self::Error => "error",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub file: String,
+    pub line: usize,   // 1-based, 0 when the problem is not tied to a line
+    pub column: usize, // 1-based, counted in characters
+    pub message: String,
+    pub severity: Severity,
+}
+
+impl Diagnostic {
+    pub fn error(file: &str, line: usize, column: usize, message: impl Into<String>) -> Self {
+        Self {
+            file: file.to_string(),
+            line,
+            column,
+            message: message.into(),
+            severity: Severity::Error,
+        }
+    }
+
+    pub fn warning(file: &str, line: usize, column: usize, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::error(file, line, column, message)
+        }
+    }
+
+    // Locates a byte offset of `source`, e.g. from a TOML span
+    pub fn at_offset(file: &str, source: &str, offset: usize, severity: Severity, message: impl Into<String>) -> Self {
+        let (line, column) = line_column(source, offset);
+        Self {
+            severity,
+            ..Self::error(file, line, column, message)
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}: {}: {}", self.file, self.severity.to_string(), self.message)
+        } else {
+            write!(f, "{}:{}:{}: {}: {}", self.file, self.line, self.column, self.severity.to_string(), self.message)
+        }
+    }
+}
+
+pub fn line_column(source: &str, offset: usize) -> (usize, usize) {
+    let mut offset = offset.min(source.len());
+    while !source.is_char_boundary(offset) {
+        offset -= 1;
+    }
+
+    let before = &source[..offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map(|pos| pos + 1).unwrap_or(0);
+    (line, before[line_start..].chars().count() + 1)
+}
+
+pub fn has_errors(diagnostics: &[Diagnostic]) -> bool {
+    diagnostics.iter().any(|diagnostic| diagnostic.severity == Severity::Error)
+}
+
+// Prints diagnostics the way rustc does, with the offending line and a caret under the column.
+// Diagnostics can point into included files, each file is read once for its lines
+pub fn print_diagnostics(diagnostics: &[Diagnostic]) {
+    let mut sources: HashMap<&str, Option<String>> = HashMap::new();
+    for diagnostic in diagnostics {
+        println!("{}: {}", diagnostic.severity.to_string(), diagnostic.message);
+
+        if diagnostic.line == 0 {
+            println!("  --> {}", diagnostic.file);
+            println!();
+            continue;
+        }
+
+        println!("  --> {}:{}:{}", diagnostic.file, diagnostic.line, diagnostic.column);
+
+        let source = sources.entry(diagnostic.file.as_str())
+            .or_insert_with(|| std::fs::read_to_string(&diagnostic.file).ok());
+        if let Some(text) = source.as_deref().and_then(|source| source.lines().nth(diagnostic.line - 1)) {
+            let gutter = " ".repeat(diagnostic.line.to_string().len());
+            println!("{} |", gutter);
+            println!("{} | {}", diagnostic.line, text);
+            println!("{} | {}^", gutter, " ".repeat(diagnostic.column.saturating_sub(1)));
+        }
+        println!();
+    }
+
+    let errors = diagnostics.iter().filter(|d| d.severity == Severity::Error).count();
+    let warnings = diagnostics.len() - errors;
+    if diagnostics.is_empty() {
+        println!("No problems found.");
+    } else {
+        println!("{} error(s), {} warning(s)", errors, warnings);
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+use std::path::Path;
+use image::imageops::FilterType;
+use log::warn;
+
+const SUPPORTED_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "bmp", "gif", "tiff"];
+
+#[derive(Debug, Clone)]
+pub struct HashedImage {
+    pub path: String,
+    pub width: u32,
+    pub height: u32,
+    pub hash: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct DuplicateCluster {
+    pub images: Vec<HashedImage>, // highest resolution first
+}
+
+impl DuplicateCluster {
+    pub fn keep(&self) -> &HashedImage {
+        &self.images[0]
+    }
+}
+
+pub fn is_supported_image(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| SUPPORTED_EXTENSIONS.contains(&ext.to_string_lossy().to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+// Recursively lists all supported images below `folder`
+pub fn images_in_folder(folder: &Path) -> Vec<String> {
+    let mut images = Vec::new();
+    let entries = match std::fs::read_dir(folder) {
+        Ok(entries) => entries,
+        Err(_) => return images,
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            images.extend(images_in_folder(&path));
+        } else if is_supported_image(&path) {
+            images.push(path.to_string_lossy().to_string());
+        }
+    }
+
+    images.sort();
+    images
+}
+
+// DCT based perceptual hash: the low frequencies of a 32x32 grayscale thumbnail
+// compared against their median, which survives rescaling and recompression
+pub fn perceptual_hash(path: &str) -> Result<HashedImage, String> {
+    let image = image::open(path).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+    let (width, height) = (image.width(), image.height());
+
+    let thumbnail = image.resize_exact(32, 32, FilterType::Triangle).to_luma8();
+    let pixels: Vec<f64> = thumbnail.pixels().map(|pixel| pixel.0[0] as f64).collect();
+
+    let mut low_frequencies = Vec::with_capacity(64);
+    for v in 0..8 {
+        for u in 0..8 {
+            low_frequencies.push(dct_coefficient(&pixels, u, v));
+        }
+    }
+
+    // The DC term only carries the average brightness
+    let mut sorted: Vec<f64> = low_frequencies[1..].to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let median = sorted[sorted.len() / 2];
+
+    let hash = low_frequencies.iter()
+        .enumerate()
+        .filter(|(_, value)| **value > median)
+        .fold(0u64, |hash, (bit, _)| hash | (1 << bit));
+
+    Ok(HashedImage {
+        path: path.to_string(),
+        width,
+        height,
+        hash,
+    })
+}
+
+fn dct_coefficient(pixels: &[f64], u: usize, v: usize) -> f64 {
+    let mut sum = 0.0;
+    for y in 0..32 {
+        for x in 0..32 {
+            sum += pixels[y * 32 + x]
+                * (((2 * x + 1) as f64 * u as f64 * std::f64::consts::PI) / 64.0).cos()
+                * (((2 * y + 1) as f64 * v as f64 * std::f64::consts::PI) / 64.0).cos();
+        }
+    }
+    sum
+}
+
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+fn find_root(parents: &mut [usize], i: usize) -> usize {
+    let mut root = i;
+    while parents[root] != root {
+        root = parents[root];
+    }
+    parents[i] = root;
+    root
+}
+
+// Groups images whose hashes differ in at most `threshold` bits
+pub fn find_duplicate_clusters(paths: &[String], threshold: u32) -> Vec<DuplicateCluster> {
+    let mut hashed = Vec::new();
+    for path in paths {
+        match perceptual_hash(path) {
+            Ok(image) => hashed.push(image),
+            Err(e) => warn!("Skipping {}", e),
+        }
+    }
+
+    let mut parents: Vec<usize> = (0..hashed.len()).collect();
+    for i in 0..hashed.len() {
+        for j in i + 1..hashed.len() {
+            if hamming_distance(hashed[i].hash, hashed[j].hash) <= threshold {
+                let (root_i, root_j) = (find_root(&mut parents, i), find_root(&mut parents, j));
+                parents[root_j] = root_i;
+            }
+        }
+    }
+
+    let mut groups: HashMap<usize, Vec<HashedImage>> = HashMap::new();
+    for (i, image) in hashed.into_iter().enumerate() {
+        let root = find_root(&mut parents, i);
+        groups.entry(root).or_default().push(image);
+    }
+
+    let mut clusters: Vec<DuplicateCluster> = groups.into_values()
+        .filter(|images| images.len() > 1)
+        .map(|mut images| {
+            images.sort_by(|a, b| {
+                (b.width as u64 * b.height as u64).cmp(&(a.width as u64 * a.height as u64))
+                    .then_with(|| a.path.cmp(&b.path))
+            });
+            DuplicateCluster { images }
+        })
+        .collect();
+
+    clusters.sort_by(|a, b| a.keep().path.cmp(&b.keep().path));
+    clusters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    // Smooth waves without symmetries, like a photo the low frequencies differ clearly from their median
+    fn waves(width: u32, height: u32, flipped: bool) -> RgbImage {
+        RgbImage::from_fn(width, height, |x, y| {
+            let u = if flipped { 1.0 - x as f64 / width as f64 } else { x as f64 / width as f64 };
+            let v = y as f64 / height as f64;
+            let value = 128.0 + 50.0 * (7.3 * u + 1.1).sin() + 40.0 * (5.9 * v + 2.3 * u).cos() + 30.0 * (11.7 * u * v).sin();
+            Rgb([value as u8, value as u8, (value / 2.0) as u8])
+        })
+    }
+
+    #[test]
+    fn rescaled_copies_are_clustered_with_the_largest_kept() {
+        let dir = std::env::temp_dir().join(format!("wallpaper-helper-duplicates-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().to_string();
+        waves(640, 400, false).save(path("large.png")).unwrap();
+        waves(320, 200, false).save(path("small.jpg")).unwrap();
+        waves(640, 400, true).save(path("flipped.png")).unwrap();
+
+        let images = images_in_folder(&dir);
+        assert_eq!(images.len(), 3);
+        let large = perceptual_hash(&path("large.png")).unwrap();
+        let small = perceptual_hash(&path("small.jpg")).unwrap();
+        let flipped = perceptual_hash(&path("flipped.png")).unwrap();
+        assert!(hamming_distance(large.hash, small.hash) <= 4);
+        assert!(hamming_distance(large.hash, flipped.hash) > 10);
+
+        let clusters = find_duplicate_clusters(&images, 4);
+        assert_eq!(clusters.len(), 1);
+        assert_eq!(clusters[0].images.len(), 2);
+        assert_eq!(clusters[0].keep().path, path("large.png"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn hamming_distance_counts_differing_bits() {
+        assert_eq!(hamming_distance(0, 0), 0);
+        assert_eq!(hamming_distance(0b1011, 0b0001), 2);
+        assert_eq!(hamming_distance(0, u64::MAX), 64);
+    }
+}
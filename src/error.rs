@@ -0,0 +1,67 @@
+use std::fmt;
+use std::io;
+
+// Named WallpaperResult because wallpaper_manager also needs the windows crate's Result
+pub type WallpaperResult<T> = std::result::Result<T, WallpaperError>;
+
+#[derive(Debug)]
+pub enum WallpaperError {
+    ProfileNotFound(String),
+    ProfileExists(String),
+    MonitorNotFound { device: String, available: Vec<String> },
+    UnsupportedFormat(String), // the file extension
+    FileMissing(String),
+    InvalidPath(String), // ~ or $VARS that cannot be expanded
+    InvalidTime { hour: u32, minute: u32 },
+    InvalidProfile(String), // inheritance cycles, unknown parents, bad generator specs and overlays
+    Bundle(String),
+    Daemon(String), // the daemon could not be reached or reported an error
+    Hook { stage: String, command: String, message: String }, // a pre_apply or post_apply command failed
+    Io { path: String, source: io::Error },
+    Backend { operation: String, hresult: i32 }, // IDesktopWallpaper call that failed
+    ConfigParse { file: String, line: usize, message: String }, // first error, line is 0 if unknown
+    ConfigWrite { file: String, message: String }, // the config could not be serialized
+}
+
+impl WallpaperError {
+    pub fn io(path: impl fmt::Display, source: io::Error) -> Self {
+        Self::Io {
+            path: path.to_string(),
+            source,
+        }
+    }
+}
+
+impl fmt::Display for WallpaperError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ProfileNotFound(name) => write!(f, "Profile '{}' not found!", name),
+            Self::ProfileExists(name) => write!(f, "Profile '{}' already exists!", name),
+            Self::MonitorNotFound { device, available } => write!(f, "Monitor device '{}' not found! Available monitors: {}",
+                                                                  device, available.join(", ")),
+            Self::UnsupportedFormat(extension) => write!(f, "Unsupported image format: {} (supported: jpg, jpeg, png, bmp, gif, tiff)",
+                                                         extension),
+            Self::FileMissing(path) => write!(f, "Wallpaper file not found: {}", path),
+            Self::InvalidTime { hour, minute } => write!(f, "Invalid time {}:{}, use 24-hour format (0-23 for hours, 0-59 for minutes)",
+                                                         hour, minute),
+            Self::InvalidPath(message) | Self::InvalidProfile(message) | Self::Bundle(message) | Self::Daemon(message) => {
+                write!(f, "{}", message)
+            }
+            Self::Hook { stage, command, message } => write!(f, "{} hook `{}` {}", stage, command, message),
+            Self::Io { path, source } => write!(f, "{}: {}", path, source),
+            Self::Backend { operation, hresult } => write!(f, "{} failed, HRESULT: 0x{:X}", operation, hresult),
+            Self::ConfigParse { file, line: 0, message } => write!(f, "{}: {}", file, message),
+            Self::ConfigParse { file, line, message } => write!(f, "{}:{}: {}", file, line, message),
+            Self::ConfigWrite { file, message } => write!(f, "{}: {}", file, message),
+        }
+    }
+}
+
+impl std::error::Error for WallpaperError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,123 @@
+// Images need at least this fraction of the monitor size before upscaling becomes visible
+const MIN_SCALE: f64 = 0.75;
+// Fill crops the image to the monitor's aspect ratio, warn once more than this fraction is lost
+const MAX_CROP: f64 = 0.2;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FitWarning {
+    TooSmall { image: (u32, u32), monitor: (u32, u32) },
+    AspectMismatch { image: (u32, u32), monitor: (u32, u32), cropped: f64 },
+}
+
+impl std::fmt::Display for FitWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TooSmall { image, monitor } => write!(f,
+                "Image is {}x{} but the monitor is {}x{}, it will look blurry",
+                image.0, image.1, monitor.0, monitor.1),
+            Self::AspectMismatch { image, monitor, cropped } => write!(f,
+                "Aspect ratio {:.2} differs from the monitor's {:.2}, Fill crops away {:.0}% of the image",
+                image.0 as f64 / image.1 as f64,
+                monitor.0 as f64 / monitor.1 as f64,
+                cropped * 100.0),
+        }
+    }
+}
+
+impl FitWarning {
+    pub fn short_label(&self) -> &'static str {
+        match self {
+            Self::TooSmall { .. } => "Low resolution",
+            Self::AspectMismatch { .. } => "Heavy crop",
+        }
+    }
+}
+
+// Fraction of the image that Fill scales out of view
+pub fn fill_crop_fraction(image: (u32, u32), monitor: (u32, u32)) -> f64 {
+    if image.0 == 0 || image.1 == 0 || monitor.0 == 0 || monitor.1 == 0 {
+        return 0.0;
+    }
+
+    let image_ratio = image.0 as f64 / image.1 as f64;
+    let monitor_ratio = monitor.0 as f64 / monitor.1 as f64;
+    1.0 - image_ratio.min(monitor_ratio) / image_ratio.max(monitor_ratio)
+}
+
+pub fn check_fit(image: (u32, u32), monitor: (u32, u32)) -> Vec<FitWarning> {
+    let mut warnings = Vec::new();
+
+    if (image.0 as f64) < monitor.0 as f64 * MIN_SCALE || (image.1 as f64) < monitor.1 as f64 * MIN_SCALE {
+        warnings.push(FitWarning::TooSmall { image, monitor });
+    }
+
+    let cropped = fill_crop_fraction(image, monitor);
+    if cropped > MAX_CROP {
+        warnings.push(FitWarning::AspectMismatch { image, monitor, cropped });
+    }
+
+    warnings
+}
+
+// 1.0 is a perfect match, lower scores lose points for cropping and for upscaling
+pub fn fit_score(image: (u32, u32), monitor: (u32, u32)) -> f64 {
+    if image.0 == 0 || image.1 == 0 || monitor.0 == 0 || monitor.1 == 0 {
+        return 0.0;
+    }
+
+    let upscale = (monitor.0 as f64 / image.0 as f64).max(monitor.1 as f64 / image.1 as f64);
+    let upscale_penalty = if upscale > 1.0 { 1.0 - 1.0 / upscale } else { 0.0 };
+
+    (1.0 - fill_crop_fraction(image, monitor)) * (1.0 - upscale_penalty)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact_matches_fit_perfectly() {
+        assert!(check_fit((1920, 1080), (1920, 1080)).is_empty());
+        assert_eq!(fit_score((1920, 1080), (1920, 1080)), 1.0);
+        // Downscaling costs nothing
+        assert!(check_fit((3840, 2160), (1920, 1080)).is_empty());
+        assert_eq!(fit_score((3840, 2160), (1920, 1080)), 1.0);
+    }
+
+    #[test]
+    fn aspect_mismatches_warn_past_the_crop_limit() {
+        // 4:3 on 16:9 loses a quarter of the image
+        let warnings = check_fit((2400, 1800), (1920, 1080));
+        assert_eq!(warnings.len(), 1);
+        match &warnings[0] {
+            FitWarning::AspectMismatch { cropped, .. } => assert!((cropped - 0.25).abs() < 1e-9),
+            other => panic!("Expected an aspect mismatch, got {:?}", other),
+        }
+        assert!((fit_score((2400, 1800), (1920, 1080)) - 0.75).abs() < 1e-9);
+
+        // 16:10 on 16:9 crops a tenth, below the limit
+        assert!(check_fit((1920, 1200), (1920, 1080)).is_empty());
+    }
+
+    #[test]
+    fn upscaling_warns_below_the_minimum_scale() {
+        // 80% of the monitor is still sharp enough, but scores lower than an exact match
+        assert!(check_fit((1536, 864), (1920, 1080)).is_empty());
+        assert!((fit_score((1536, 864), (1920, 1080)) - 0.8).abs() < 1e-9);
+
+        let warnings = check_fit((960, 540), (1920, 1080));
+        assert_eq!(warnings, vec![FitWarning::TooSmall { image: (960, 540), monitor: (1920, 1080) }]);
+        assert!((fit_score((960, 540), (1920, 1080)) - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn zero_sizes_never_fit() {
+        assert_eq!(fit_score((0, 0), (1920, 1080)), 0.0);
+        assert_eq!(fit_score((1920, 0), (1920, 1080)), 0.0);
+        assert_eq!(fit_score((1920, 1080), (0, 0)), 0.0);
+        assert_eq!(fill_crop_fraction((1920, 1080), (0, 1080)), 0.0);
+
+        assert_eq!(check_fit((0, 0), (1920, 1080)), vec![FitWarning::TooSmall { image: (0, 0), monitor: (1920, 1080) }]);
+        assert!(check_fit((1920, 1080), (0, 0)).is_empty());
+    }
+}
@@ -0,0 +1,221 @@
+use std::path::Path;
+use image::{Rgba, RgbaImage};
+use crate::overlay::{format_color, parse_color};
+
+// Profile entries starting with this prefix are rendered instead of loaded from disk
+pub const GENERATOR_PREFIX: &str = "generate:";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeneratorSpec {
+    Solid([u8; 4]),
+    Linear { from: [u8; 4], to: [u8; 4], angle: f32 },
+    Radial { inner: [u8; 4], outer: [u8; 4] },
+    Noise { from: [u8; 4], to: [u8; 4], scale: f32, seed: u32 },
+}
+
+pub fn is_generator_spec(wallpaper_path: &str) -> bool {
+    wallpaper_path.starts_with(GENERATOR_PREFIX)
+}
+
+impl GeneratorSpec {
+    // Parses specs like "generate:linear:#000000:#3366ff:90"
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let body = spec.strip_prefix(GENERATOR_PREFIX)
+            .ok_or_else(|| format!("Not a generator spec: {}", spec))?;
+        let parts: Vec<&str> = body.split(':').map(|part| part.trim()).collect();
+
+        let number = |value: &str, what: &str| value.parse::<f32>()
+            .map_err(|_| format!("Invalid {} in generator spec: {}", what, value));
+
+        match parts.as_slice() {
+            ["solid", color] => Ok(Self::Solid(parse_color(color)?)),
+            ["linear", from, to] => Ok(Self::Linear {
+                from: parse_color(from)?,
+                to: parse_color(to)?,
+                angle: 90.0,
+            }),
+            ["linear", from, to, angle] => Ok(Self::Linear {
+                from: parse_color(from)?,
+                to: parse_color(to)?,
+                angle: number(angle, "angle")?,
+            }),
+            ["radial", inner, outer] => Ok(Self::Radial {
+                inner: parse_color(inner)?,
+                outer: parse_color(outer)?,
+            }),
+            ["noise", from, to] => Ok(Self::Noise {
+                from: parse_color(from)?,
+                to: parse_color(to)?,
+                scale: 64.0,
+                seed: 0,
+            }),
+            ["noise", from, to, scale] => Ok(Self::Noise {
+                from: parse_color(from)?,
+                to: parse_color(to)?,
+                scale: number(scale, "scale")?.max(1.0),
+                seed: 0,
+            }),
+            ["noise", from, to, scale, seed] => Ok(Self::Noise {
+                from: parse_color(from)?,
+                to: parse_color(to)?,
+                scale: number(scale, "scale")?.max(1.0),
+                seed: seed.parse::<u32>()
+                    .map_err(|_| format!("Invalid seed in generator spec: {}", seed))?,
+            }),
+            _ => Err(format!("Unknown generator spec: {}", spec)),
+        }
+    }
+
+    pub fn to_spec(&self) -> String {
+        match self {
+            Self::Solid(color) => format!("{}solid:{}", GENERATOR_PREFIX, format_color(*color)),
+            Self::Linear { from, to, angle } => format!("{}linear:{}:{}:{}",
+                                                        GENERATOR_PREFIX, format_color(*from), format_color(*to), angle),
+            Self::Radial { inner, outer } => format!("{}radial:{}:{}",
+                                                     GENERATOR_PREFIX, format_color(*inner), format_color(*outer)),
+            Self::Noise { from, to, scale, seed } => format!("{}noise:{}:{}:{}:{}",
+                                                             GENERATOR_PREFIX, format_color(*from), format_color(*to), scale, seed),
+        }
+    }
+
+    pub fn render(&self, width: u32, height: u32) -> RgbaImage {
+        let (w, h) = (width.max(1) as f32, height.max(1) as f32);
+
+        match self {
+            Self::Solid(color) => RgbaImage::from_pixel(width, height, Rgba(*color)),
+            Self::Linear { from, to, angle } => {
+                let (sin, cos) = angle.to_radians().sin_cos();
+                // Project the corners onto the gradient direction so the ramp spans the whole image
+                let extent = (w * cos).abs() + (h * sin).abs();
+                RgbaImage::from_fn(width, height, |x, y| {
+                    let along = (x as f32 - w / 2.0) * cos + (y as f32 - h / 2.0) * sin;
+                    Rgba(lerp_color(*from, *to, along / extent.max(1.0) + 0.5))
+                })
+            }
+            Self::Radial { inner, outer } => {
+                let radius = (w * w + h * h).sqrt() / 2.0;
+                RgbaImage::from_fn(width, height, |x, y| {
+                    let (dx, dy) = (x as f32 - w / 2.0, y as f32 - h / 2.0);
+                    Rgba(lerp_color(*inner, *outer, (dx * dx + dy * dy).sqrt() / radius))
+                })
+            }
+            Self::Noise { from, to, scale, seed } => {
+                RgbaImage::from_fn(width, height, |x, y| {
+                    let t = fractal_noise(x as f32 / scale, y as f32 / scale, *seed);
+                    Rgba(lerp_color(*from, *to, t))
+                })
+            }
+        }
+    }
+}
+
+fn lerp_color(from: [u8; 4], to: [u8; 4], t: f32) -> [u8; 4] {
+    let t = t.clamp(0.0, 1.0);
+    let mut color = [0u8; 4];
+    for (i, channel) in color.iter_mut().enumerate() {
+        *channel = (from[i] as f32 + (to[i] as f32 - from[i] as f32) * t).round() as u8;
+    }
+    color
+}
+
+fn hash(x: i32, y: i32, seed: u32) -> f32 {
+    let mut h = (x as u32).wrapping_mul(0x27d4_eb2d)
+        ^ (y as u32).wrapping_mul(0x1656_67b1)
+        ^ seed.wrapping_mul(0x9e37_79b9);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    (h & 0x00ff_ffff) as f32 / 0x00ff_ffff as f32
+}
+
+fn value_noise(x: f32, y: f32, seed: u32) -> f32 {
+    let (x0, y0) = (x.floor() as i32, y.floor() as i32);
+    let (fx, fy) = (x - x.floor(), y - y.floor());
+    let (sx, sy) = (fx * fx * (3.0 - 2.0 * fx), fy * fy * (3.0 - 2.0 * fy));
+
+    let top = hash(x0, y0, seed) + (hash(x0 + 1, y0, seed) - hash(x0, y0, seed)) * sx;
+    let bottom = hash(x0, y0 + 1, seed) + (hash(x0 + 1, y0 + 1, seed) - hash(x0, y0 + 1, seed)) * sx;
+    top + (bottom - top) * sy
+}
+
+fn fractal_noise(x: f32, y: f32, seed: u32) -> f32 {
+    let mut total = 0.0;
+    let mut amplitude = 0.5;
+    let mut frequency = 1.0;
+    let mut norm = 0.0;
+
+    for octave in 0..4 {
+        total += value_noise(x * frequency, y * frequency, seed.wrapping_add(octave)) * amplitude;
+        norm += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+
+    total / norm
+}
+
+pub fn render_generator(spec: &str, width: u32, height: u32, output: &Path) -> Result<(), String> {
+    let image = GeneratorSpec::parse(spec)?.render(width, height);
+
+    if let Some(parent) = output.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+
+    image.save_with_format(output, image::ImageFormat::Png)
+        .map_err(|e| format!("Failed to write {}: {}", output.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn specs_survive_saving_and_loading() {
+        for spec in [
+            "generate:solid:#102030",
+            "generate:solid:#10203080",
+            "generate:linear:#000000:#3366ff:45",
+            "generate:radial:#ffffff:#000000",
+            "generate:noise:#000000:#ffffff:32:7",
+        ] {
+            let parsed = GeneratorSpec::parse(spec).unwrap();
+            assert_eq!(GeneratorSpec::parse(&parsed.to_spec()).unwrap(), parsed, "{}", spec);
+        }
+        // Left out arguments get their defaults written out
+        assert_eq!(GeneratorSpec::parse("generate:linear:#000000:#ffffff").unwrap().to_spec(),
+                   "generate:linear:#000000:#ffffff:90");
+    }
+
+    #[test]
+    fn invalid_specs_are_errors() {
+        for spec in [
+            "solid:#000000",
+            "generate:",
+            "generate:solid",
+            "generate:solid:#00000",
+            "generate:linear:#000000:#ffffff:steep",
+            "generate:noise:#000000:#ffffff:8:-1",
+            "generate:spiral:#000000:#ffffff",
+        ] {
+            assert!(GeneratorSpec::parse(spec).is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn renders_at_the_requested_size() {
+        let solid = GeneratorSpec::parse("generate:solid:#102030").unwrap().render(64, 48);
+        assert_eq!(solid.dimensions(), (64, 48));
+        assert!(solid.pixels().all(|pixel| pixel.0 == [0x10, 0x20, 0x30, 0xff]));
+
+        // 0 degrees runs from left to right
+        let linear = GeneratorSpec::parse("generate:linear:#000000:#ffffff:0").unwrap().render(100, 10);
+        assert_eq!(linear.dimensions(), (100, 10));
+        assert!(linear.get_pixel(0, 5).0[0] < 10);
+        assert!(linear.get_pixel(99, 5).0[0] > 245);
+
+        let noise = GeneratorSpec::parse("generate:noise:#000000:#ffffff:8:1").unwrap();
+        assert_eq!(noise.render(33, 17).dimensions(), (33, 17));
+        assert_eq!(noise.render(33, 17), noise.render(33, 17));
+    }
+}
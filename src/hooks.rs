@@ -0,0 +1,294 @@
+use std::fmt;
+use std::io;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use log::{info, warn};
+use crate::error::{WallpaperError, WallpaperResult};
+
+pub const DEFAULT_HOOK_TIMEOUT: u64 = 30; // seconds, per command
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookStage {
+    PreApply,
+    PostApply,
+}
+
+impl fmt::Display for HookStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PreApply => write!(f, "pre_apply"),
+            Self::PostApply => write!(f, "post_apply"),
+        }
+    }
+}
+
+// Shell commands run around applying a profile, from [hooks] or a profile's own hooks table
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Hooks {
+    pub pre_apply: Vec<String>,
+    pub post_apply: Vec<String>,
+    pub timeout: Option<u64>, // seconds, DEFAULT_HOOK_TIMEOUT if not set
+}
+
+impl Hooks {
+    pub fn is_empty(&self) -> bool {
+        self.pre_apply.is_empty() && self.post_apply.is_empty() && self.timeout.is_none()
+    }
+
+    // Every kind of hook `other` sets replaces the one here, like a child profile's fit mode
+    pub fn override_with(&mut self, other: &Hooks) {
+        if !other.pre_apply.is_empty() {
+            self.pre_apply = other.pre_apply.clone();
+        }
+        if !other.post_apply.is_empty() {
+            self.post_apply = other.post_apply.clone();
+        }
+        if other.timeout.is_some() {
+            self.timeout = other.timeout;
+        }
+    }
+}
+
+// WALLPAPER_MONITOR_<n> and WALLPAPER_IMAGE_<n> are numbered from 1, device names are not usable in variable names.
+// `success` is only known after applying.
+pub fn hook_environment(stage: HookStage, profile_name: &str, images: &[(String, String)], success: Option<bool>)
+                        -> Vec<(String, String)> {
+    let mut environment = vec![
+        ("WALLPAPER_HOOK".to_string(), stage.to_string()),
+        ("WALLPAPER_PROFILE".to_string(), profile_name.to_string()),
+        ("WALLPAPER_MONITOR_COUNT".to_string(), images.len().to_string()),
+    ];
+
+    for (i, (device, image)) in images.iter().enumerate() {
+        environment.push((format!("WALLPAPER_MONITOR_{}", i + 1), device.clone()));
+        environment.push((format!("WALLPAPER_IMAGE_{}", i + 1), image.clone()));
+    }
+
+    if let Some(success) = success {
+        environment.push(("WALLPAPER_SUCCESS".to_string(), if success { "1" } else { "0" }.to_string()));
+    }
+    environment
+}
+
+fn shell(command: &str) -> Command {
+    let mut shell = if cfg!(windows) { Command::new("cmd") } else { Command::new("sh") };
+    shell.arg(if cfg!(windows) { "/C" } else { "-c" }).arg(command);
+    shell
+}
+
+// A hook and everything it starts: its own process group on Unix, a job object on Windows
+struct ProcessTree {
+    child: Child,
+    #[cfg(windows)]
+    job: winapi::um::winnt::HANDLE, // null if the hook could not be put into a job
+}
+
+impl ProcessTree {
+    #[cfg(not(windows))]
+    fn spawn(command: &mut Command) -> io::Result<Self> {
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(command, 0);
+        Ok(Self { child: command.spawn()? })
+    }
+
+    #[cfg(windows)]
+    fn spawn(command: &mut Command) -> io::Result<Self> {
+        use std::os::windows::io::AsRawHandle;
+        use winapi::um::jobapi2::{AssignProcessToJobObject, CreateJobObjectW};
+
+        let child = command.spawn()?;
+        let mut job = unsafe { CreateJobObjectW(std::ptr::null_mut(), std::ptr::null()) };
+        if !job.is_null() && unsafe { AssignProcessToJobObject(job, child.as_raw_handle() as _) } == 0 {
+            unsafe { winapi::um::handleapi::CloseHandle(job) };
+            job = std::ptr::null_mut();
+        }
+        if job.is_null() {
+            warn!("Processes started by hook {} are not stopped on timeout: {}", child.id(), io::Error::last_os_error());
+        }
+        Ok(Self { child, job })
+    }
+
+    fn kill(&mut self) {
+        #[cfg(unix)]
+        unsafe {
+            libc::killpg(self.child.id() as libc::pid_t, libc::SIGKILL);
+        }
+        #[cfg(windows)]
+        if !self.job.is_null() {
+            unsafe { winapi::um::jobapi2::TerminateJobObject(self.job, 1) };
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(windows)]
+impl Drop for ProcessTree {
+    fn drop(&mut self) {
+        if !self.job.is_null() {
+            unsafe { winapi::um::handleapi::CloseHandle(self.job) };
+        }
+    }
+}
+
+fn run_hook(stage: HookStage, command: &str, timeout: Duration, environment: &[(String, String)]) -> WallpaperResult<()> {
+    info!("Running {} hook: {}", stage, command);
+    let hook_error = |message: String| WallpaperError::Hook {
+        stage: stage.to_string(),
+        command: command.to_string(),
+        message,
+    };
+
+    // Output goes to stderr, so that `apply --format json` stays readable
+    let mut process = ProcessTree::spawn(shell(command)
+        .envs(environment.iter().map(|(name, value)| (name, value)))
+        .stdin(Stdio::null())
+        .stdout(io::stderr()))
+        .map_err(|e| hook_error(format!("could not be started: {}", e)))?;
+
+    let started = Instant::now();
+    loop {
+        match process.child.try_wait() {
+            Ok(Some(status)) if status.success() => return Ok(()),
+            Ok(Some(status)) => return Err(hook_error(format!("failed with {}", status))),
+            Ok(None) if started.elapsed() >= timeout => {
+                process.kill();
+                return Err(hook_error(format!("timed out after {}s", timeout.as_secs())));
+            }
+            Ok(None) => thread::sleep(POLL_INTERVAL),
+            Err(e) => return Err(hook_error(e.to_string())),
+        }
+    }
+}
+
+// The first failing pre_apply command cancels the apply; post_apply failures are only logged.
+// Both block the calling thread, see HookThreads for running post_apply hooks in the background.
+pub fn run_hooks(stage: HookStage, hooks: &Hooks, environment: &[(String, String)]) -> WallpaperResult<()> {
+    let timeout = Duration::from_secs(hooks.timeout.unwrap_or(DEFAULT_HOOK_TIMEOUT));
+
+    match stage {
+        HookStage::PreApply => {
+            for command in &hooks.pre_apply {
+                run_hook(stage, command, timeout, environment)?;
+            }
+        }
+        HookStage::PostApply => {
+            for command in &hooks.post_apply {
+                if let Err(e) = run_hook(stage, command, timeout, environment) {
+                    warn!("{}", e);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// post_apply hooks run on their own threads so that the window and the daemon loop do not wait for them;
+// one shot commands wait before exiting, or the hooks would be cut off
+#[derive(Clone, Default)]
+pub struct HookThreads(Arc<Mutex<Vec<JoinHandle<()>>>>);
+
+impl HookThreads {
+    pub fn spawn(&self, run: impl FnOnce() + Send + 'static) {
+        let mut threads = self.0.lock().unwrap();
+        threads.retain(|thread| !thread.is_finished());
+        threads.push(thread::spawn(run));
+    }
+
+    pub fn wait(&self) {
+        let threads = std::mem::take(&mut *self.0.lock().unwrap());
+        for thread in threads {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(environment: &[(String, String)]) -> Vec<String> {
+        environment.iter().map(|(name, value)| format!("{}={}", name, value)).collect()
+    }
+
+    #[test]
+    fn monitors_are_numbered_from_one() {
+        let images = [("\\\\.\\DISPLAY1".to_string(), "/a.png".to_string()), ("\\\\.\\DISPLAY2".to_string(), "/b.png".to_string())];
+        assert_eq!(names(&hook_environment(HookStage::PreApply, "work", &images, None)), [
+            "WALLPAPER_HOOK=pre_apply",
+            "WALLPAPER_PROFILE=work",
+            "WALLPAPER_MONITOR_COUNT=2",
+            "WALLPAPER_MONITOR_1=\\\\.\\DISPLAY1",
+            "WALLPAPER_IMAGE_1=/a.png",
+            "WALLPAPER_MONITOR_2=\\\\.\\DISPLAY2",
+            "WALLPAPER_IMAGE_2=/b.png",
+        ]);
+    }
+
+    #[test]
+    fn success_is_only_set_after_applying() {
+        assert_eq!(names(&hook_environment(HookStage::PostApply, "work", &[], Some(false))), [
+            "WALLPAPER_HOOK=post_apply",
+            "WALLPAPER_PROFILE=work",
+            "WALLPAPER_MONITOR_COUNT=0",
+            "WALLPAPER_SUCCESS=0",
+        ]);
+    }
+
+    fn test_file(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("wallpaper-helper-hooks-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    // Gone, or a zombie nobody has reaped yet
+    #[cfg(unix)]
+    fn is_running(pid: &str) -> bool {
+        let output = Command::new("ps").args(["-o", "stat=", "-p", pid]).output().unwrap();
+        let state = String::from_utf8_lossy(&output.stdout);
+        !state.trim().is_empty() && !state.trim().starts_with('Z')
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn timeouts_kill_everything_the_hook_started() {
+        let pid_file = test_file("pid");
+        let command = format!("sleep 60 & echo $! > '{}'; sleep 60", pid_file.display());
+
+        let started = Instant::now();
+        let result = run_hook(HookStage::PreApply, &command, Duration::from_millis(500), &[]);
+        assert!(matches!(result, Err(WallpaperError::Hook { .. })), "{:?}", result);
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+        let gone = Instant::now();
+        while is_running(pid.trim()) {
+            assert!(gone.elapsed() < Duration::from_secs(5), "sleep {} is still running", pid.trim());
+            thread::sleep(POLL_INTERVAL);
+        }
+        std::fs::remove_file(&pid_file).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn failing_pre_apply_hooks_stop_the_apply() {
+        let marker = test_file("marker");
+        let hooks = Hooks {
+            pre_apply: vec!["exit 3".to_string(), format!("touch '{}'", marker.display())],
+            post_apply: vec!["exit 3".to_string(), format!("touch '{}'", marker.display())],
+            timeout: Some(5),
+        };
+
+        let error = run_hooks(HookStage::PreApply, &hooks, &[]).unwrap_err();
+        assert!(error.to_string().contains("exit 3"), "{}", error);
+        assert!(!marker.exists());
+
+        // post_apply failures are logged and the next command still runs
+        run_hooks(HookStage::PostApply, &hooks, &[]).unwrap();
+        assert!(marker.exists());
+        std::fs::remove_file(&marker).unwrap();
+    }
+}
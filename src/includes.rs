@@ -0,0 +1,355 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use crate::config::{unspanned, ConfigFile, MonitorConfig, ProfileConfig};
+use crate::diagnostics::Diagnostic;
+use crate::overlay::Overlay;
+use crate::paths::{expand_vars, PathContext};
+use crate::wallpaper_manager::WallpaperProfile;
+
+// Drop-in directory next to the main config, every *.toml in it is read in name order
+pub const DROP_IN_DIR: &str = "profiles.d";
+
+// Which file every part of a profile came from. Keys are "extends", "fit", "hooks",
+// "wallpaper:<device>" and "overlays:<device>".
+#[derive(Debug, Clone, Default)]
+pub struct ProfileOrigin {
+    pub owner: String, // highest precedence file defining the profile, new entries are saved there
+    pub fields: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct IncludedFile {
+    pub filename: String,
+    pub document: ConfigFile,
+    pub saved: String, // its profiles as save_config would write them, to skip unchanged files
+}
+
+// Included files and drop-ins, lowest precedence first; the main config always wins
+#[derive(Debug, Clone, Default)]
+pub struct ConfigSources {
+    pub included: Vec<IncludedFile>,
+    pub origins: HashMap<String, ProfileOrigin>,
+}
+
+impl ConfigSources {
+    pub fn is_included(&self, filename: &str) -> bool {
+        self.included.iter().any(|file| file.filename == filename)
+    }
+
+    // Entries without a recorded source belong to the profile's owner, new profiles to the main config
+    pub fn writes_to(&self, profile_name: &str, key: &str, filename: &str) -> bool {
+        let source = self.origins.get(profile_name)
+            .map(|origin| origin.fields.get(key).unwrap_or(&origin.owner));
+
+        match source {
+            Some(source) if self.is_included(source) => source == filename,
+            _ => !self.is_included(filename),
+        }
+    }
+}
+
+pub fn wallpaper_key(device: &str) -> String {
+    format!("wallpaper:{}", device)
+}
+
+pub fn overlays_key(device: &str) -> String {
+    format!("overlays:{}", device)
+}
+
+// Explicit includes in the order they are listed, then the drop-in directory.
+// Relative includes start at the config directory and may use ~ and $VARS.
+pub fn include_files(paths: &PathContext, main_file: &str, includes: &[String], diagnostics: &mut Vec<Diagnostic>) -> Vec<String> {
+    let mut files = Vec::new();
+
+    for include in includes {
+        match expand_vars(include) {
+            Ok(expanded) => {
+                let path = PathBuf::from(expanded);
+                let path = if path.is_absolute() { path } else { paths.config_dir.join(path) };
+                files.push(path.to_string_lossy().to_string());
+            }
+            Err(e) => diagnostics.push(Diagnostic::warning(main_file, 0, 0, format!("include '{}' skipped: {}", include, e))),
+        }
+    }
+
+    let drop_in_dir = paths.config_dir.join(DROP_IN_DIR);
+    if let Ok(entries) = std::fs::read_dir(&drop_in_dir) {
+        let mut drop_ins: Vec<PathBuf> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("toml")))
+            .collect();
+        drop_ins.sort();
+        files.extend(drop_ins.into_iter().map(|path| path.to_string_lossy().to_string()));
+    }
+
+    files
+}
+
+// Included files only contribute profiles, everything else in them would be silently lost on save
+pub fn check_included_document(filename: &str, document: &ConfigFile, diagnostics: &mut Vec<Diagnostic>) {
+    let mut ignored: Vec<&str> = document.extra.keys().map(|key| key.as_str()).collect();
+    if !document.include.is_empty() {
+        ignored.push("include");
+    }
+    if !document.schedule.is_empty() {
+        ignored.push("schedule");
+    }
+    if !document.templates.is_empty() {
+        ignored.push("templates");
+    }
+    if document.wallpaper_root.is_some() {
+        ignored.push("wallpaper_root");
+    }
+    if document.backups.is_some() {
+        ignored.push("backups");
+    }
+    if !document.hooks.is_empty() {
+        ignored.push("hooks");
+    }
+
+    if !ignored.is_empty() {
+        diagnostics.push(Diagnostic::warning(filename, 0, 0,
+                                             format!("only [profiles] are read from included files, ignoring: {}",
+                                                     ignored.join(", "))));
+    }
+}
+
+// Merges `profile` from `filename` over what lower precedence files defined and
+// reports every value it replaces with a different one
+pub fn merge_profile(profiles: &mut HashMap<String, WallpaperProfile>, sources: &mut ConfigSources, filename: &str,
+                     profile: WallpaperProfile, diagnostics: &mut Vec<Diagnostic>) {
+    let name = profile.name.clone();
+    let merged = profiles.entry(name.clone()).or_insert_with(|| WallpaperProfile::new(&name));
+    let origin = sources.origins.entry(name.clone()).or_default();
+    origin.owner = filename.to_string();
+
+    let mut conflict = |origin: &mut ProfileOrigin, key: String, what: String, differs: bool| {
+        if let Some(previous) = origin.fields.insert(key, filename.to_string()) {
+            if differs {
+                diagnostics.push(Diagnostic::warning(filename, 0, 0,
+                                                     format!("profile '{}': {} overrides the one from {}", name, what, previous)));
+            }
+        }
+    };
+
+    if let Some(extends) = profile.extends {
+        conflict(origin, "extends".to_string(), format!("extends '{}'", extends), merged.extends.as_ref() != Some(&extends));
+        merged.extends = Some(extends);
+    }
+
+    if let Some(fit) = profile.fit {
+        let differs = merged.fit.is_some_and(|previous| previous != fit);
+        conflict(origin, "fit".to_string(), format!("fit {}", fit.to_string()), differs);
+        merged.fit = Some(fit);
+    }
+
+    if !profile.hooks.is_empty() {
+        let differs = !merged.hooks.is_empty() && merged.hooks != profile.hooks;
+        conflict(origin, "hooks".to_string(), "hooks".to_string(), differs);
+        merged.hooks.override_with(&profile.hooks);
+    }
+
+    for (device, wallpaper) in profile.monitor_wallpapers {
+        let differs = merged.monitor_wallpapers.get(&device).is_some_and(|previous| *previous != wallpaper);
+        conflict(origin, wallpaper_key(&device), format!("wallpaper for {}", device), differs);
+        merged.monitor_wallpapers.insert(device, wallpaper);
+    }
+
+    for (device, overlays) in profile.overlays {
+        if overlays.is_empty() {
+            continue;
+        }
+        let specs = |overlays: &Vec<Overlay>| overlays.iter().map(|overlay| overlay.to_spec()).collect::<Vec<String>>();
+        let differs = merged.overlays.get(&device).is_some_and(|previous| specs(previous) != specs(&overlays));
+        conflict(origin, overlays_key(&device), format!("overlays for {}", device), differs);
+        merged.overlays.insert(device, overlays);
+    }
+}
+
+// The [profiles] tables `filename` should contain, keeping unknown keys from `previous`
+pub fn profiles_for_file(profiles: &HashMap<String, WallpaperProfile>, sources: &ConfigSources, filename: &str,
+                         previous: &ConfigFile) -> BTreeMap<String, ProfileConfig> {
+    let mut tables = BTreeMap::new();
+
+    for (name, profile) in profiles {
+        let writes = |key: &str| sources.writes_to(name, key, filename);
+        let previous_profile = previous.profiles.get(name);
+
+        let mut profile_config = ProfileConfig {
+            extends: profile.extends.clone().filter(|_| writes("extends")).map(unspanned),
+            fit: profile.fit.filter(|_| writes("fit")).map(|fit| unspanned(fit.to_string().to_string())),
+            hooks: previous_profile.map(|p| p.hooks.clone()).unwrap_or_default(), // only edited in the file itself
+            extra: previous_profile.map(|p| p.extra.clone()).unwrap_or_default(),
+            ..ProfileConfig::default()
+        };
+
+        // Devices with only overlays override what another file or a parent draws on its wallpaper
+        let mut devices: Vec<&String> = profile.monitor_wallpapers.keys()
+            .chain(profile.overlays.iter().filter(|(_, overlays)| !overlays.is_empty()).map(|(device, _)| device))
+            .collect();
+        devices.sort();
+        devices.dedup();
+
+        for device in devices {
+            let path = profile.monitor_wallpapers.get(device)
+                .filter(|_| writes(&wallpaper_key(device)))
+                .map(|wallpaper| unspanned(wallpaper.clone()));
+            let overlays: Vec<_> = profile.overlays.get(device)
+                .filter(|_| writes(&overlays_key(device)))
+                .map(|overlays| overlays.iter().map(|overlay| unspanned(overlay.to_spec())).collect())
+                .unwrap_or_default();
+
+            if path.is_none() && overlays.is_empty() {
+                continue;
+            }
+
+            profile_config.monitors.insert(device.clone(), MonitorConfig {
+                path,
+                overlays,
+                extra: previous_profile
+                    .and_then(|p| p.monitors.get(device))
+                    .map(|monitor| monitor.extra.clone())
+                    .unwrap_or_default(),
+            });
+        }
+
+        // Values another file overrides are still part of this one and must survive the save
+        if let Some(previous_profile) = previous_profile {
+            if profile_config.extends.is_none() && !writes("extends") {
+                profile_config.extends = previous_profile.extends.clone();
+            }
+            if profile_config.fit.is_none() && !writes("fit") {
+                profile_config.fit = previous_profile.fit.clone();
+            }
+
+            for (device, previous_monitor) in &previous_profile.monitors {
+                let monitor = profile_config.monitors.entry(device.clone()).or_insert_with(|| MonitorConfig {
+                    path: None,
+                    overlays: Vec::new(),
+                    extra: previous_monitor.extra.clone(),
+                });
+                if monitor.path.is_none() && !writes(&wallpaper_key(device)) {
+                    monitor.path = previous_monitor.path.clone();
+                }
+                if monitor.overlays.is_empty() && !writes(&overlays_key(device)) {
+                    monitor.overlays = previous_monitor.overlays.clone();
+                }
+            }
+            profile_config.monitors.retain(|_, monitor| monitor.path.is_some() || !monitor.overlays.is_empty());
+        }
+
+        let owns = sources.writes_to(name, "", filename);
+        if owns || profile_config.extends.is_some() || profile_config.fit.is_some() || !profile_config.monitors.is_empty()
+            || !profile_config.hooks.is_empty() {
+            tables.insert(name.clone(), profile_config);
+        }
+    }
+
+    tables
+}
+
+pub fn is_same_file(a: &str, b: &str) -> bool {
+    match (Path::new(a).canonicalize(), Path::new(b).canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wallpaper_manager::DesktopWallpaperPosition;
+
+    fn profile(name: &str, wallpapers: &[(&str, &str)], fit: Option<DesktopWallpaperPosition>) -> WallpaperProfile {
+        let mut profile = WallpaperProfile::new(name);
+        for (device, path) in wallpapers {
+            profile.monitor_wallpapers.insert(device.to_string(), path.to_string());
+        }
+        profile.fit = fit;
+        profile
+    }
+
+    fn wallpapers(tables: &BTreeMap<String, ProfileConfig>, name: &str) -> Vec<(String, String)> {
+        tables[name].monitors.iter()
+            .filter_map(|(device, monitor)| monitor.path.as_ref().map(|path| (device.clone(), path.get_ref().clone())))
+            .collect()
+    }
+
+    #[test]
+    fn includes_come_before_drop_ins_in_name_order() {
+        let dir = std::env::temp_dir().join(format!("wallpaper-helper-includes-test-{}", std::process::id()));
+        let drop_ins = dir.join(DROP_IN_DIR);
+        std::fs::create_dir_all(&drop_ins).unwrap();
+        for name in ["b.toml", "a.TOML", "notes.txt"] {
+            std::fs::write(drop_ins.join(name), "").unwrap();
+        }
+
+        let main_file = dir.join("config.toml").to_string_lossy().to_string();
+        let absolute = std::env::temp_dir().join("shared.toml").to_string_lossy().to_string();
+        let includes = vec!["screens.toml".to_string(), absolute.clone(), "$WALLPAPER_HELPER_UNSET_TEST/x.toml".to_string()];
+        let mut diagnostics = Vec::new();
+        let files = include_files(&PathContext::new(None, &main_file), &main_file, &includes, &mut diagnostics);
+
+        let in_dir = |path: PathBuf| path.to_string_lossy().to_string();
+        assert_eq!(files, [in_dir(dir.join("screens.toml")), absolute, in_dir(drop_ins.join("a.TOML")), in_dir(drop_ins.join("b.toml"))]);
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].message.contains("$WALLPAPER_HELPER_UNSET_TEST"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn merged_profiles_save_back_into_the_files_they_came_from() {
+        let mut sources = ConfigSources::default();
+        sources.included.push(IncludedFile {
+            filename: "shared.toml".to_string(),
+            document: ConfigFile::default(),
+            saved: String::new(),
+        });
+
+        let mut profiles = HashMap::new();
+        let mut diagnostics = Vec::new();
+        merge_profile(&mut profiles, &mut sources, "shared.toml",
+                      profile("work", &[("DISPLAY1", "a.png"), ("DISPLAY2", "old.png")], Some(DesktopWallpaperPosition::Fill)),
+                      &mut diagnostics);
+        merge_profile(&mut profiles, &mut sources, "config.toml",
+                      profile("work", &[("DISPLAY2", "b.png")], Some(DesktopWallpaperPosition::Center)),
+                      &mut diagnostics);
+
+        let work = &profiles["work"];
+        assert_eq!(work.monitor_wallpapers["DISPLAY1"], "a.png");
+        assert_eq!(work.monitor_wallpapers["DISPLAY2"], "b.png");
+        assert_eq!(work.fit, Some(DesktopWallpaperPosition::Center));
+        assert_eq!(diagnostics.len(), 2, "{:?}", diagnostics);
+        assert!(diagnostics.iter().all(|diagnostic| diagnostic.file == "config.toml"));
+
+        // Edited entries go back where they came from, new ones into the profile's owner
+        assert!(sources.writes_to("work", &wallpaper_key("DISPLAY1"), "shared.toml"));
+        assert!(sources.writes_to("work", &wallpaper_key("DISPLAY2"), "config.toml"));
+        assert!(sources.writes_to("work", &wallpaper_key("DISPLAY3"), "config.toml"));
+        assert!(!sources.writes_to("work", &wallpaper_key("DISPLAY3"), "shared.toml"));
+        assert!(sources.writes_to("home", "", "config.toml"));
+
+        let work = profiles.get_mut("work").unwrap();
+        work.monitor_wallpapers.insert("DISPLAY1".to_string(), "c.png".to_string());
+        work.monitor_wallpapers.insert("DISPLAY3".to_string(), "d.png".to_string());
+        profiles.insert("home".to_string(), profile("home", &[("DISPLAY1", "e.png")], None));
+
+        let previous_shared: ConfigFile = toml::from_str(
+            "[profiles.work]\nfit = \"fill\"\n[profiles.work.monitors.DISPLAY1]\npath = \"a.png\"\n[profiles.work.monitors.DISPLAY2]\npath = \"old.png\"\n"
+        ).unwrap();
+        let shared = profiles_for_file(&profiles, &sources, "shared.toml", &previous_shared);
+        assert_eq!(shared.keys().collect::<Vec<_>>(), ["work"]);
+        assert_eq!(wallpapers(&shared, "work"), [("DISPLAY1".to_string(), "c.png".to_string()),
+                                                 ("DISPLAY2".to_string(), "old.png".to_string())]);
+        // The main config overrides the fit, the include keeps its own
+        assert_eq!(shared["work"].fit.as_ref().unwrap().get_ref(), "fill");
+
+        let main = profiles_for_file(&profiles, &sources, "config.toml", &ConfigFile::default());
+        assert_eq!(main.keys().collect::<Vec<_>>(), ["home", "work"]);
+        assert_eq!(wallpapers(&main, "work"), [("DISPLAY2".to_string(), "b.png".to_string()),
+                                               ("DISPLAY3".to_string(), "d.png".to_string())]);
+        assert_eq!(main["work"].fit.as_ref().unwrap().get_ref(), "Center");
+    }
+}
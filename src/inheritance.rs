@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use crate::wallpaper_manager::WallpaperProfile;
+
+// A profile with everything it inherits merged in, and which profile each value came from
+#[derive(Debug, Clone)]
+pub struct ResolvedProfile {
+    pub profile: WallpaperProfile,
+    pub chain: Vec<String>, // the profile itself first, then its parents
+    pub wallpaper_sources: HashMap<String, String>, // deviceName -> profile that set the wallpaper
+    pub overlay_sources: HashMap<String, String>, // deviceName -> profile that set the overlays
+    pub fit_source: Option<String>,
+}
+
+// Follows `extends` upwards from `name`, failing on unknown parents and on cycles
+pub fn parent_chain(profiles: &HashMap<String, WallpaperProfile>, name: &str) -> Result<Vec<String>, String> {
+    let mut chain: Vec<String> = Vec::new();
+    let mut current = name;
+
+    loop {
+        if chain.iter().any(|seen| seen == current) {
+            chain.push(current.to_string());
+            return Err(format!("profile inheritance cycle: {}", chain.join(" -> ")));
+        }
+
+        let profile = match profiles.get(current) {
+            Some(profile) => profile,
+            None => return Err(match chain.last() {
+                Some(child) => format!("profile '{}' extends unknown profile '{}'", child, current),
+                None => format!("Profile '{}' not found!", current),
+            }),
+        };
+
+        chain.push(current.to_string());
+        match &profile.extends {
+            Some(parent) => current = parent,
+            None => return Ok(chain),
+        }
+    }
+}
+
+// Applies the chain from the root down, so every profile overrides what its parents set.
+// Overlays are inherited per monitor unless the child lists its own.
+pub fn resolve_profile(profiles: &HashMap<String, WallpaperProfile>, name: &str) -> Result<ResolvedProfile, String> {
+    let chain = parent_chain(profiles, name)?;
+
+    let mut resolved = ResolvedProfile {
+        profile: WallpaperProfile {
+            extends: profiles[name].extends.clone(),
+            ..WallpaperProfile::new(name)
+        },
+        chain: chain.clone(),
+        wallpaper_sources: HashMap::new(),
+        overlay_sources: HashMap::new(),
+        fit_source: None,
+    };
+
+    for source in chain.iter().rev() {
+        let profile = &profiles[source];
+
+        resolved.profile.hooks.override_with(&profile.hooks);
+
+        if profile.fit.is_some() {
+            resolved.profile.fit = profile.fit;
+            resolved.fit_source = Some(source.clone());
+        }
+
+        for (device, wallpaper) in &profile.monitor_wallpapers {
+            resolved.profile.monitor_wallpapers.insert(device.clone(), wallpaper.clone());
+            resolved.wallpaper_sources.insert(device.clone(), source.clone());
+        }
+
+        for (device, overlays) in &profile.overlays {
+            if !overlays.is_empty() {
+                resolved.profile.overlays.insert(device.clone(), overlays.clone());
+                resolved.overlay_sources.insert(device.clone(), source.clone());
+            }
+        }
+    }
+
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(name: &str, extends: Option<&str>, wallpapers: &[(&str, &str)]) -> (String, WallpaperProfile) {
+        let mut profile = WallpaperProfile::new(name);
+        profile.extends = extends.map(str::to_string);
+        for (device, path) in wallpapers {
+            profile.monitor_wallpapers.insert(device.to_string(), path.to_string());
+        }
+        (name.to_string(), profile)
+    }
+
+    #[test]
+    fn children_override_their_parents() {
+        let profiles = HashMap::from([
+            profile("base", None, &[("M1", "base1.png"), ("M2", "base2.png")]),
+            profile("work", Some("base"), &[("M2", "work2.png")]),
+        ]);
+        let resolved = resolve_profile(&profiles, "work").unwrap();
+        assert_eq!(resolved.chain, ["work", "base"]);
+        assert_eq!(resolved.profile.monitor_wallpapers["M1"], "base1.png");
+        assert_eq!(resolved.profile.monitor_wallpapers["M2"], "work2.png");
+        assert_eq!(resolved.wallpaper_sources["M1"], "base");
+        assert_eq!(resolved.wallpaper_sources["M2"], "work");
+    }
+
+    #[test]
+    fn cycles_and_unknown_parents_are_errors() {
+        let profiles = HashMap::from([
+            profile("a", Some("b"), &[]),
+            profile("b", Some("c"), &[]),
+            profile("c", Some("a"), &[]),
+            profile("self", Some("self"), &[]),
+            profile("orphan", Some("missing"), &[]),
+        ]);
+        assert_eq!(resolve_profile(&profiles, "a").unwrap_err(), "profile inheritance cycle: a -> b -> c -> a");
+        assert_eq!(resolve_profile(&profiles, "self").unwrap_err(), "profile inheritance cycle: self -> self");
+        assert_eq!(resolve_profile(&profiles, "orphan").unwrap_err(), "profile 'orphan' extends unknown profile 'missing'");
+        assert_eq!(resolve_profile(&profiles, "none").unwrap_err(), "Profile 'none' not found!");
+    }
+}
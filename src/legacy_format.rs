@@ -0,0 +1,383 @@
+use std::collections::HashMap;
+use crate::config::{check_profile_parents, check_schedule_profiles, check_schedule_time, check_wallpaper_path, LoadedConfig};
+use crate::diagnostics::Diagnostic;
+use crate::overlay::Overlay;
+use crate::paths::PathContext;
+use crate::wallpaper_manager::{ScheduleEntry, WallpaperProfile};
+
+// Quoting for the line based config.txt format. Bare fields are still read literally,
+// so files written by older versions load unchanged (device IDs keep their backslashes).
+// Fields that would be ambiguous are written in double quotes with \" \\ \n and \r escapes.
+
+pub fn needs_quotes(field: &str, delimiters: &[char]) -> bool {
+    field.is_empty()
+        || field.trim() != field
+        || field.starts_with('"')
+        || field.starts_with('[')
+        || field.contains(['\n', '\r'])
+        || field.contains(delimiters)
+}
+
+pub fn quote(field: &str) -> String {
+    let mut quoted = String::with_capacity(field.len() + 2);
+    quoted.push('"');
+    for c in field.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+pub fn quote_field(field: &str, delimiters: &[char]) -> String {
+    if needs_quotes(field, delimiters) {
+        quote(field)
+    } else {
+        field.to_string()
+    }
+}
+
+// Reads one field from the start of `input` and returns it with the unparsed rest.
+// A bare field ends before the first `delimiter`, the rest still starts with it.
+pub fn read_field(input: &str, delimiter: Option<char>) -> Result<(String, &str), String> {
+    let rest = match input.strip_prefix('"') {
+        Some(rest) => rest,
+        None => {
+            return match delimiter.and_then(|delimiter| input.find(delimiter)) {
+                Some(pos) => Ok((input[..pos].to_string(), &input[pos..])),
+                None => Ok((input.to_string(), "")),
+            };
+        }
+    };
+
+    let mut value = String::new();
+    let mut chars = rest.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((value, &rest[i + 1..])),
+            '\\' => match chars.next() {
+                Some((_, 'n')) => value.push('\n'),
+                Some((_, 'r')) => value.push('\r'),
+                Some((_, escaped)) => value.push(escaped),
+                None => break,
+            },
+            _ => value.push(c),
+        }
+    }
+
+    Err("Unterminated quoted string".to_string())
+}
+
+// Reads a complete value, quoted values must not be followed by anything else
+pub fn read_value(input: &str) -> Result<String, String> {
+    if !input.starts_with('"') {
+        return Ok(input.to_string());
+    }
+
+    match read_field(input, None)? {
+        (value, "") => Ok(value),
+        (_, rest) => Err(format!("Unexpected text after quoted value: {}", rest)),
+    }
+}
+
+// Splits "key=value" where the key may be quoted; bare keys end at the first '='
+pub fn read_key_value(input: &str) -> Result<(String, String), String> {
+    let (key, rest) = read_field(input, Some('='))?;
+    match rest.strip_prefix('=') {
+        Some(value) => Ok((key, read_value(value)?)),
+        None => Err("Expected '=' after key".to_string()),
+    }
+}
+
+pub fn write_key_value(key: &str, value: &str) -> String {
+    format!("{}={}", quote_field(key, &['=']), quote_field(value, &[]))
+}
+
+// Schedule rows are "profile,hour,minute,enabled". Unquoted profile names are split
+// from the right, so names with commas written by older versions still load.
+pub fn read_schedule_row(line: &str) -> Result<(String, [&str; 3]), String> {
+    if line.starts_with('"') {
+        let (name, rest) = read_field(line, Some(','))?;
+        let fields: Vec<&str> = rest.strip_prefix(',')
+            .ok_or_else(|| "Expected ',' after profile name".to_string())?
+            .split(',')
+            .collect();
+
+        return match fields.as_slice() {
+            [hour, minute, enabled] => Ok((name, [*hour, *minute, *enabled])),
+            _ => Err(format!("Expected 4 fields but found {}", fields.len() + 1)),
+        };
+    }
+
+    let fields: Vec<&str> = line.rsplitn(4, ',').collect();
+    match fields.as_slice() {
+        [enabled, minute, hour, name] => Ok((name.to_string(), [*hour, *minute, *enabled])),
+        _ => Err(format!("Expected 4 fields but found {}", fields.len())),
+    }
+}
+
+pub fn write_schedule_row(profile_name: &str, hour: u32, minute: u32, enabled: bool) -> String {
+    format!("{},{},{},{}",
+            quote_field(profile_name, &[',']),
+            hour,
+            minute,
+            if enabled { 1 } else { 0 })
+}
+
+// Only unindented "[NAME]" lines with an upper case name start a section, so
+// quoted values or indented lines that happen to look like "[...]" stay data
+pub fn section_header(line: &str) -> Option<&str> {
+    let name = line.strip_prefix('[')?.strip_suffix(']')?;
+    if !name.is_empty() && name.chars().all(|c| c.is_ascii_uppercase() || c == '_') {
+        Some(name)
+    } else {
+        None
+    }
+}
+
+pub fn write_legacy_config(profiles: &HashMap<String, WallpaperProfile>, schedule: &[ScheduleEntry],
+                           theme_templates: &[(String, String)]) -> String {
+    let mut content = String::new();
+
+    // Profiles
+    content.push_str("[PROFILES]\n");
+    for (name, profile) in profiles {
+        content.push_str(&format!("PROFILE:{}\n", quote_field(name, &[])));
+        if let Some(parent) = &profile.extends {
+            content.push_str(&format!("EXTENDS:{}\n", quote_field(parent, &[])));
+        }
+
+        // A device line without "=path" only carries overlays for a wallpaper inherited from the parent
+        let mut devices: Vec<&String> = profile.monitor_wallpapers.keys()
+            .chain(profile.overlays.iter().filter(|(_, overlays)| !overlays.is_empty()).map(|(device, _)| device))
+            .collect();
+        devices.sort();
+        devices.dedup();
+
+        for device in devices {
+            match profile.monitor_wallpapers.get(device) {
+                Some(wallpaper) => content.push_str(&format!("  {}\n", write_key_value(device, wallpaper))),
+                None => content.push_str(&format!("  {}\n", quote_field(device, &['=']))),
+            }
+
+            for overlay in profile.overlays.get(device).into_iter().flatten() {
+                content.push_str(&format!("    {}\n", write_key_value("overlay", &overlay.to_spec())));
+            }
+        }
+    }
+
+    // Theme templates
+    content.push_str("[TEMPLATES]\n");
+    for (template, output) in theme_templates {
+        content.push_str(&format!("{}\n", write_key_value(template, output)));
+    }
+
+    // Schedule
+    content.push_str("[SCHEDULE]\n");
+    for entry in schedule {
+        content.push_str(&format!("{}\n", write_schedule_row(&entry.profile_name, entry.hour, entry.minute, entry.enabled)));
+    }
+
+    content
+}
+
+pub fn parse_legacy_config(filename: &str, source: &str) -> (LoadedConfig, Vec<Diagnostic>) {
+    let mut loaded = LoadedConfig {
+        paths: PathContext::new(None, filename),
+        ..LoadedConfig::default()
+    };
+    let mut diagnostics = Vec::new();
+    let mut schedule_lines = Vec::new();
+    let mut parent_lines = HashMap::new();
+
+    let mut current_section = String::new();
+    let mut current_profile = String::new();
+    let mut current_device = String::new();
+
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        // The old loader skipped every line it could not read, so none of these stop a config.txt from loading
+        let warning = |column: usize, message: String| Diagnostic::warning(filename, line_number, column, message);
+
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(section) = section_header(line) {
+            if !matches!(section, "PROFILES" | "SCHEDULE" | "TEMPLATES") {
+                diagnostics.push(Diagnostic::warning(filename, line_number, 2,
+                                                     format!("unknown section [{}], its lines are ignored", section)));
+            }
+            current_section = section.to_string();
+            current_profile.clear();
+            current_device.clear();
+            continue;
+        }
+
+        match current_section.as_str() {
+            "PROFILES" => {
+                if let Some(name) = line.strip_prefix("PROFILE:") {
+                    current_device.clear();
+                    match read_value(name) {
+                        Ok(name) => {
+                            if loaded.profiles.contains_key(&name) {
+                                diagnostics.push(Diagnostic::warning(filename, line_number, 9,
+                                                                     format!("profile '{}' is defined more than once", name)));
+                            }
+                            loaded.profiles.insert(name.clone(), WallpaperProfile::new(&name));
+                            current_profile = name;
+                        }
+                        Err(e) => {
+                            diagnostics.push(warning(9, e));
+                            current_profile.clear();
+                        }
+                    }
+                } else if let Some(parent) = line.strip_prefix("EXTENDS:") {
+                    match (read_value(parent), loaded.profiles.get_mut(&current_profile)) {
+                        (Ok(parent), Some(profile)) => {
+                            profile.extends = Some(parent);
+                            parent_lines.insert(current_profile.clone(), (filename.to_string(), line_number, 9));
+                        }
+                        (Ok(_), None) => diagnostics.push(warning(1, "EXTENDS outside of a PROFILE".to_string())),
+                        (Err(e), _) => diagnostics.push(warning(9, e)),
+                    }
+                } else if let Some(spec) = line.strip_prefix("    overlay=") {
+                    if current_device.is_empty() {
+                        diagnostics.push(warning(5, "overlay without a wallpaper line before it".to_string()));
+                        continue;
+                    }
+
+                    match read_value(spec).and_then(|spec| Overlay::parse(&spec)) {
+                        Ok(overlay) => {
+                            if let Some(profile) = loaded.profiles.get_mut(&current_profile) {
+                                profile.overlays.entry(current_device.clone()).or_default().push(overlay);
+                            }
+                        }
+                        Err(e) => diagnostics.push(warning(13, e)),
+                    }
+                } else if let Some(mapping) = line.strip_prefix("  ") {
+                    if current_profile.is_empty() {
+                        diagnostics.push(warning(3, "wallpaper line outside of a PROFILE".to_string()));
+                        continue;
+                    }
+
+                    if let Ok((device, "")) = read_field(mapping, Some('=')) {
+                        if !device.is_empty() {
+                            current_device = device;
+                            continue;
+                        }
+                    }
+
+                    match read_key_value(mapping) {
+                        Ok((device, wallpaper)) => {
+                            if let Some((_, message)) = check_wallpaper_path(&loaded.paths, &wallpaper) {
+                                let column = line.find('=').unwrap_or(2) + 2;
+                                diagnostics.push(warning(column, message));
+                            }
+
+                            current_device = device.clone();
+                            if let Some(profile) = loaded.profiles.get_mut(&current_profile) {
+                                profile.monitor_wallpapers.insert(device, wallpaper);
+                            }
+                        }
+                        Err(e) => diagnostics.push(warning(3, format!("expected device=path: {}", e))),
+                    }
+                } else {
+                    diagnostics.push(warning(1, "expected 'PROFILE:<name>', 'EXTENDS:<name>' or an indented device=path line".to_string()));
+                }
+            }
+            "SCHEDULE" => {
+                let (profile_name, [hour, minute, enabled]) = match read_schedule_row(line) {
+                    Ok(row) => row,
+                    Err(e) => {
+                        diagnostics.push(warning(1, format!("expected profile,hour,minute,enabled: {}", e)));
+                        continue;
+                    }
+                };
+
+                match (hour.parse::<u32>(), minute.parse::<u32>(), enabled.parse::<i32>()) {
+                    (Ok(hour), Ok(minute), Ok(enabled_int)) => {
+                        if let Some(message) = check_schedule_time(hour, minute) {
+                            diagnostics.push(warning(1, message));
+                        }
+
+                        loaded.schedule.push(ScheduleEntry {
+                            profile_name,
+                            hour,
+                            minute,
+                            enabled: enabled_int == 1,
+                        });
+                        schedule_lines.push((line_number, 1));
+                    }
+                    _ => diagnostics.push(warning(1, "hour, minute and enabled must be numbers".to_string())),
+                }
+            }
+            "TEMPLATES" => {
+                match read_key_value(line) {
+                    Ok(template) => loaded.theme_templates.push(template),
+                    Err(e) => diagnostics.push(warning(1, format!("expected template=output: {}", e))),
+                }
+            }
+            "" => diagnostics.push(warning(1, "line outside of any section".to_string())),
+            _ => {}
+        }
+    }
+
+    check_profile_parents(filename, &loaded, &parent_lines, &mut diagnostics);
+    check_schedule_profiles(filename, &loaded, &schedule_lines, &mut diagnostics);
+    (loaded, diagnostics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn written_configs_read_back_unchanged() {
+        let tricky = ["plain", "with space", "a=b", "# not a comment", "\"quoted\"", "back\\slash", " padded ", "[section]"];
+        let mut profiles = HashMap::new();
+        for (i, name) in tricky.iter().enumerate() {
+            let mut profile = WallpaperProfile::new(name);
+            if i > 0 {
+                profile.extends = Some(tricky[i - 1].to_string());
+            }
+            profile.monitor_wallpapers.insert(format!("\\\\.\\DISPLAY{}", i), format!("C:\\walls\\{}.png", name));
+            profile.overlays.insert(format!("overlay only {}", name), vec![Overlay::parse("hostname;size=20").unwrap()]);
+            profiles.insert(name.to_string(), profile);
+        }
+        let schedule: Vec<ScheduleEntry> = tricky.iter().enumerate()
+            .map(|(i, name)| ScheduleEntry { profile_name: name.to_string(), hour: i as u32, minute: 30, enabled: i % 2 == 0 })
+            .collect();
+        let templates = vec![("C:\\templates\\a=b #1.txt".to_string(), "out \"1\".txt".to_string())];
+
+        let written = write_legacy_config(&profiles, &schedule, &templates);
+        let (loaded, diagnostics) = parse_legacy_config("config.txt", &written);
+        // The wallpapers do not exist, anything else means a line was misread
+        assert!(diagnostics.iter().all(|d| d.message.starts_with("wallpaper file not found")), "{:?}", diagnostics);
+
+        assert_eq!(loaded.profiles.len(), profiles.len());
+        for (name, profile) in &profiles {
+            let read = &loaded.profiles[name];
+            assert_eq!(read.extends, profile.extends);
+            assert_eq!(read.monitor_wallpapers, profile.monitor_wallpapers);
+            assert_eq!(read.overlays, profile.overlays);
+        }
+        let read_schedule: Vec<_> = loaded.schedule.iter().map(|e| (&e.profile_name, e.hour, e.minute, e.enabled)).collect();
+        let schedule: Vec<_> = schedule.iter().map(|e| (&e.profile_name, e.hour, e.minute, e.enabled)).collect();
+        assert_eq!(read_schedule, schedule);
+        assert_eq!(loaded.theme_templates, templates);
+    }
+
+    #[test]
+    fn fields_are_only_quoted_when_needed() {
+        assert_eq!(quote_field("\\\\.\\DISPLAY1", &['=']), "\\\\.\\DISPLAY1");
+        assert_eq!(quote_field("a=b", &['=']), "\"a=b\"");
+        assert_eq!(read_field("\"a=\\\"b\\\"\"=c", Some('=')).unwrap(), ("a=\"b\"".to_string(), "=c"));
+        assert!(read_field("\"open", None).is_err());
+    }
+}
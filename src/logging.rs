@@ -0,0 +1,45 @@
+use std::io::{self, Write};
+use flexi_logger::{detailed_format, Cleanup, Criterion, DeferredNow, Duplicate, FileSpec, FlexiLoggerError,
+                   LogSpecification, Logger, LoggerHandle, Naming};
+use log::{Level, LevelFilter, Record};
+
+// Messages go to <name>_rCURRENT.<ext>, which is renamed to <name>_r00000.<ext> and so on at this size
+const LOG_FILE_SIZE: u64 = 1024 * 1024;
+const LOG_FILES_KEPT: usize = 5;
+
+// -q only shows errors, -v adds debug messages like the monitor dump, -vv everything
+pub fn log_level(verbose: u8, quiet: bool) -> LevelFilter {
+    match (quiet, verbose) {
+        (true, _) => LevelFilter::Error,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    }
+}
+
+// Info messages read like plain status lines, everything else names its level and module
+fn stderr_format(w: &mut dyn Write, _now: &mut DeferredNow, record: &Record) -> io::Result<()> {
+    match record.level() {
+        Level::Info => write!(w, "{}", record.args()),
+        level => write!(w, "{} [{}] {}", level, record.target(), record.args()),
+    }
+}
+
+// RUST_LOG overrides `level` per module, e.g. RUST_LOG=info,wallpaper_helper::config=trace
+// The returned handle has to live until the program exits
+pub fn init_logging(level: LevelFilter, log_file: Option<&str>) -> Result<LoggerHandle, FlexiLoggerError> {
+    let spec = LogSpecification::env_or_parse(level.as_str().to_lowercase())?;
+    let logger = Logger::with(spec).format_for_stderr(stderr_format);
+
+    let logger = match log_file {
+        Some(path) => logger
+            .log_to_file(FileSpec::try_from(path)?)
+            .rotate(Criterion::Size(LOG_FILE_SIZE), Naming::Numbers, Cleanup::KeepLogFiles(LOG_FILES_KEPT))
+            .append()
+            .format_for_files(detailed_format)
+            .duplicate_to_stderr(Duplicate::from(level)),
+        None => logger.log_to_stderr(),
+    };
+
+    logger.start()
+}
@@ -1,18 +1,52 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::io::Write;
 use std::ops::Index;
 use std::rc::Rc;
+use std::time::Duration;
 use gtk4 as gtk;
 use gtk::prelude::*;
-use gtk::{glib, ApplicationWindow, Application, Button, Box, Image};
+use gtk::{gio, glib, ApplicationWindow, Application, Button, Box, Image};
 use gtk4::glib::property::PropertyGet;
+use clap::Parser;
+use log::{debug, error, info, warn};
+use crate::cli::Cli;
+use crate::apply_report::ApplyReport;
+use crate::completions::complete_if_requested;
+use crate::config::resolve_config_path;
+use crate::config_watcher::ConfigWatcher;
+use crate::daemon::DaemonClient;
+use crate::logging::{init_logging, log_level};
 use crate::wallpaper_manager::WallpaperManager;
 
+mod apply_report;
+mod bundle;
+mod cli;
+mod completions;
+mod config;
+mod config_watcher;
+mod daemon;
+mod dbus;
+mod diagnostics;
+mod duplicates;
+mod error;
+mod fit;
+mod generator;
+mod hooks;
+mod includes;
+mod inheritance;
+mod legacy_format;
+mod logging;
+mod output;
+mod overlay;
+mod palette;
+mod paths;
 mod wallpaper_manager;
 
-fn build_ui(app: &gtk::Application) {
+fn build_ui(app: &gtk::Application, config_file: &str) {
     let mut manager = Rc::new(RefCell::new(WallpaperManager::new()));
-    manager.borrow_mut().load_config("config.txt");
+    if let Err(e) = manager.borrow_mut().load_config(config_file) {
+        error!("{}", e);
+    }
 
     let window = ApplicationWindow::builder()
         .application(app)
@@ -26,6 +60,7 @@ fn build_ui(app: &gtk::Application) {
     grid.set_column_spacing(5);
 
     let mut wallpapers: Vec<Image> = Vec::new();
+    let mut badges: Vec<gtk::Label> = Vec::new();
 
     for (i, monitor) in manager.borrow().monitors.iter().enumerate()
     {
@@ -39,13 +74,19 @@ fn build_ui(app: &gtk::Application) {
 
         let button = Button::with_label(&*monitor.device_name);
         button.connect_clicked(move |_| {
-            eprintln!("Clicked!");
+            debug!("Clicked!");
         });
 
         let label = gtk::Label::new(Some(&monitor.device_name));
 
+        // Shows resolution and aspect ratio warnings for the previewed profile
+        let badge = gtk::Label::new(None);
+        badge.add_css_class("warning");
+        badges.push(badge.clone());
+
         grid.attach(&label, i as i32, 0, 1, 1);
         grid.attach(&current_wallpaper, i as i32, 1, 1, 1);
+        grid.attach(&badge, i as i32, 2, 1, 1);
     }
 
     let foo : Vec<String> = manager.borrow().profiles.keys().cloned().collect();
@@ -55,19 +96,47 @@ fn build_ui(app: &gtk::Application) {
     profile_selector.connect_selected_notify({
         let dropdown = profile_selector.clone();
         let wallpapers_cloned = wallpapers.clone();
+        let badges_cloned = badges.clone();
         let manager_clone = manager.clone();
+        // Previews of a profile that is no longer selected are dropped when they finish
+        let selection = Rc::new(Cell::new(0u64));
         move |_| {
             if let Some(selected_item) = dropdown.selected_item()
             {
                 if let Ok(string_object) = selected_item.downcast::<gtk::StringObject>()
                 {
                     let selected_text = string_object.string();
-                    if let Some(selected_profile) = manager_clone.borrow().profiles.get(selected_text.as_str())
+                    let manager_ref = manager_clone.borrow();
+                    selection.set(selection.get() + 1);
+                    for (i, monitor) in manager_ref.monitors.iter().enumerate()
                     {
-                        for (i, pair) in selected_profile.monitor_wallpapers.iter().enumerate()
+                        // Generators and overlays are rendered small and off the GTK thread
+                        if let Some(job) = manager_ref.preview_job(selected_text.as_str(), &monitor.device_name)
                         {
-                            wallpapers_cloned[i].set_from_file(Some(pair.1));
+                            let wallpaper = wallpapers_cloned[i].clone();
+                            let (selection, started) = (selection.clone(), selection.get());
+                            glib::spawn_future_local(async move {
+                                match gio::spawn_blocking(move || job.render()).await {
+                                    Ok(Ok(preview)) if selection.get() == started => wallpaper.set_from_file(Some(preview)),
+                                    Ok(Err(e)) => warn!("No preview: {}", e),
+                                    _ => {}
+                                }
+                            });
                         }
+
+                        let resolved = manager_ref.resolve_profile(selected_text.as_str()).ok();
+                        let warnings = resolved.as_ref()
+                            .and_then(|resolved| resolved.profile.monitor_wallpapers.get(&monitor.device_name))
+                            .map(|path| manager_ref.fit_warnings(&monitor.device_name, path))
+                            .unwrap_or_default();
+                        let badge_text: Vec<String> = warnings.iter()
+                            .map(|warning| format!("⚠ {}", warning.short_label()))
+                            .collect();
+                        badges_cloned[i].set_text(&badge_text.join("  "));
+                        badges_cloned[i].set_tooltip_text(Some(&warnings.iter()
+                            .map(|warning| warning.to_string())
+                            .collect::<Vec<String>>()
+                            .join("\n")));
                     }
                 }
             }
@@ -75,22 +144,101 @@ fn build_ui(app: &gtk::Application) {
     });
 
     let apply_button = Button::with_label("Apply Selected profile");
+    let apply_status = gtk::Label::new(None);
     apply_button.connect_clicked({
         let dropdown = profile_selector.clone();
         let manager_clone = manager.clone();
-        move |_|
+        let apply_status = apply_status.clone();
+        move |button|
             {
                 if let Some(selected_item) = dropdown.selected_item()
                 {
                     if let Ok(string_object) = selected_item.downcast::<gtk::StringObject>()
                     {
-                        let selected_text = string_object.string();
-                        manager_clone.borrow_mut().apply_profile(&selected_text);
+                        let selected_text = string_object.string().to_string();
+                        let manager = manager_clone.clone();
+                        let apply_status = apply_status.clone();
+                        let button = button.clone();
+                        button.set_sensitive(false);
+                        apply_status.set_text(&format!("Applying '{}'...", selected_text));
+
+                        // The daemon and the pre_apply hooks can take a while, the window keeps drawing meanwhile
+                        glib::spawn_future_local(async move {
+                            let name = selected_text.clone();
+                            // A running daemon owns the wallpapers, the window only asks it
+                            let from_daemon = gio::spawn_blocking(move || DaemonClient::connect().map(|mut daemon| daemon.apply(&name))).await;
+                            let report = match from_daemon {
+                                Ok(Some(result)) => result.unwrap_or_else(|e| ApplyReport::failed(&selected_text, e.to_string())),
+                                _ => {
+                                    // The monitor handles stay on this thread, only the hooks move to a worker
+                                    let hooks = manager.borrow().pre_apply_hooks(&selected_text);
+                                    match hooks {
+                                        Ok(hooks) => match gio::spawn_blocking(move || hooks.run()).await {
+                                            Ok(Ok(())) => manager.borrow().apply_profile_after_hooks(&selected_text),
+                                            Ok(Err(e)) => {
+                                                error!("{}, profile '{}' not applied", e, selected_text);
+                                                ApplyReport::cancelled(&selected_text, e.to_string())
+                                            }
+                                            Err(_) => ApplyReport::failed(&selected_text, "pre_apply hooks panicked".to_string()),
+                                        },
+                                        Err(e) => ApplyReport::failed(&selected_text, e.to_string()),
+                                    }
+                                }
+                            };
+
+                            // Hovering the summary lists what went wrong per monitor
+                            let failures: Vec<String> = report.monitors.iter()
+                                .filter_map(|monitor| monitor.error.as_ref().map(|e| format!("{}: {}", monitor.device, e)))
+                                .chain(report.fit.iter().filter_map(|fit| fit.error.as_ref().map(|e| format!("fit: {}", e))))
+                                .collect();
+                            apply_status.set_text(&report.summary());
+                            apply_status.set_tooltip_text(if failures.is_empty() { None } else { Some(failures.join("\n")) }.as_deref());
+                            button.set_sensitive(true);
+                        });
                     }
                 }
             }
     });
 
+    // Pick up hand edits and dotfile syncs of the config without a restart
+    let watch_paths = manager.borrow().config_watch_paths(config_file);
+    match ConfigWatcher::new(&watch_paths) {
+        Ok(mut watcher) => {
+            let dropdown = profile_selector.clone();
+            let manager_clone = manager.clone();
+            let config_file = config_file.to_string();
+            glib::timeout_add_local(Duration::from_millis(500), move || {
+                if !watcher.changed() {
+                    return glib::ControlFlow::Continue;
+                }
+
+                let reloaded = manager_clone.borrow_mut().reload_config(&config_file);
+                if let Err(e) = watcher.update(&manager_clone.borrow().config_watch_paths(&config_file)) {
+                    warn!("Not watching all of {} for changes: {}", config_file, e);
+                }
+                if reloaded.is_ok() {
+                    let selected = dropdown.selected_item()
+                        .and_downcast::<gtk::StringObject>()
+                        .map(|string_object| string_object.string().to_string());
+
+                    let names: Vec<String> = manager_clone.borrow().profiles.keys().cloned().collect();
+                    let name_strings: Vec<&str> = names.iter().map(|s| s.as_str()).collect();
+                    if let Some(list) = dropdown.model().and_downcast::<gtk::StringList>() {
+                        list.splice(0, list.n_items(), name_strings.as_slice());
+                    }
+
+                    if let Some(position) = selected.and_then(|name| names.iter().position(|n| *n == name)) {
+                        dropdown.set_selected(position as u32);
+                    }
+                    // Redraw the previews even if the selection did not move
+                    dropdown.notify("selected");
+                }
+                glib::ControlFlow::Continue
+            });
+        }
+        Err(e) => warn!("Not watching {} for changes: {}", config_file, e),
+    }
+
     let new_button = Button::with_label("New profile");
     let parent_clone = window.clone();
     let mut manager_clone = manager.clone();
@@ -122,8 +270,10 @@ fn build_ui(app: &gtk::Application) {
             if resp == gtk::ResponseType::Ok {
                 let name = entry.text().to_string();
                 if !name.trim().is_empty() {
-                    println!("Creating profile: {}", name);
-                    m_clone.borrow_mut().create_profile(name.as_str());
+                    info!("Creating profile: {}", name);
+                    if let Err(e) = m_clone.borrow_mut().create_profile(name.as_str()) {
+                        error!("{}", e);
+                    }
                 }
             }
             d.close();
@@ -138,142 +288,37 @@ fn build_ui(app: &gtk::Application) {
 
     v_box.append(&grid);
     v_box.append(&apply_button);
+    v_box.append(&apply_status);
     window.set_child(Some(&v_box));
 
     window.present();
 }
 
-// Example usage and CLI interface
 fn main() {
+    complete_if_requested();
+    let cli = Cli::parse();
+    let config_file = resolve_config_path(cli.config.as_deref());
+
+    // Without a terminal, e.g. for the window or the scheduler, --log-file is the only place messages end up
+    let _logger = match init_logging(log_level(cli.verbose, cli.quiet), cli.log_file.as_deref()) {
+        Ok(handle) => Some(handle),
+        Err(e) => {
+            eprintln!("Logging could not be set up: {}", e);
+            None
+        }
+    };
+
+    if let Some(command) = cli.command {
+        std::process::exit(cli::run(command, &config_file, cli.format));
+    }
+
     let app = Application::builder()
         .application_id("org.example.HelloWorld")
         .build();
 
-    app.connect_activate(build_ui);
-
-    app.run(); //blocks
-
-    /*println!("Rust Wallpaper Manager");
-    println!("======================");
-
-    loop {
-        println!("\nCommands:");
-        println!("1. monitors     - List available monitors");
-        println!("2. create       - Create new profile");
-        println!("3. set          - Set wallpaper for monitor in profile");
-        println!("4. apply        - Apply profile");
-        println!("5. profiles     - List profiles");
-        println!("6. schedule     - Add schedule");
-        println!("7. schedules    - List schedules");
-        println!("8. start_sched  - Start scheduler");
-        println!("9. stop_sched   - Stop scheduler");
-        println!("10. save        - Save configuration");
-        println!("11. load        - Load configuration");
-        println!("12. quit        - Exit program");
-
-        print!("\nEnter command: ");
-        std::io::stdout().flush().unwrap();
-
-        let mut input = String::new();
-        std::io::stdin().read_line(&mut input).unwrap();
-        let command = input.trim();
-
-        match command {
-            "1" | "monitors" => {
-                manager.print_monitors();
-            }
-            "2" | "create" => {
-                print!("Enter profile name: ");
-                std::io::stdout().flush().unwrap();
-                let mut profile_name = String::new();
-                std::io::stdin().read_line(&mut profile_name).unwrap();
-                manager.create_profile(profile_name.trim());
-            }
-            "3" | "set" => {
-                print!("Enter profile name: ");
-                std::io::stdout().flush().unwrap();
-                let mut profile_name = String::new();
-                std::io::stdin().read_line(&mut profile_name).unwrap();
-
-                print!("Enter device name: ");
-                std::io::stdout().flush().unwrap();
-                let mut device_name = String::new();
-                std::io::stdin().read_line(&mut device_name).unwrap();
-
-                print!("Enter wallpaper path: ");
-                std::io::stdout().flush().unwrap();
-                let mut wallpaper_path = String::new();
-                std::io::stdin().read_line(&mut wallpaper_path).unwrap();
-
-                manager.set_wallpaper_in_profile(
-                    profile_name.trim(),
-                    device_name.trim(),
-                    wallpaper_path.trim()
-                );
-            }
-            "4" | "apply" => {
-                print!("Enter profile name to apply: ");
-                std::io::stdout().flush().unwrap();
-                let mut profile_name = String::new();
-                std::io::stdin().read_line(&mut profile_name).unwrap();
-                manager.apply_profile(profile_name.trim());
-            }
-            "5" | "profiles" => {
-                manager.list_profiles();
-            }
-            "6" | "schedule" => {
-                print!("Enter profile name: ");
-                std::io::stdout().flush().unwrap();
-                let mut profile_name = String::new();
-                std::io::stdin().read_line(&mut profile_name).unwrap();
-
-                print!("Enter hour (0-23): ");
-                std::io::stdout().flush().unwrap();
-                let mut hour_str = String::new();
-                std::io::stdin().read_line(&mut hour_str).unwrap();
-
-                print!("Enter minute (0-59): ");
-                std::io::stdout().flush().unwrap();
-                let mut minute_str = String::new();
-                std::io::stdin().read_line(&mut minute_str).unwrap();
-
-                if let (Ok(hour), Ok(minute)) = (hour_str.trim().parse(), minute_str.trim().parse()) {
-                    manager.add_schedule(profile_name.trim(), hour, minute);
-                } else {
-                    println!("Invalid time format!");
-                }
-            }
-            "7" | "schedules" => {
-                manager.list_schedule();
-            }
-            "8" | "start_sched" => {
-                manager.start_scheduler();
-            }
-            "9" | "stop_sched" => {
-                manager.stop_scheduler();
-            }
-            "10" | "save" => {
-                print!("Enter config filename: ");
-                std::io::stdout().flush().unwrap();
-                let mut filename = String::new();
-                std::io::stdin().read_line(&mut filename).unwrap();
-                manager.save_config(filename.trim());
-            }
-            "11" | "load" => {
-                print!("Enter config filename: ");
-                std::io::stdout().flush().unwrap();
-                let mut filename = String::new();
-                std::io::stdin().read_line(&mut filename).unwrap();
-                manager.load_config(filename.trim());
-            }
-            "12" | "quit" | "exit" => {
-                manager.stop_scheduler();
-                println!("Goodbye!");
-                break;
-            }
-            _ => {
-                println!("Unknown command: {}", command);
-            }
-        }
-    }*/
-}
\ No newline at end of file
+    app.connect_activate(move |app| build_ui(app, &config_file));
+
+    // GTK would reject our own options, so it only gets the program name
+    let program: Vec<String> = std::env::args().take(1).collect();
+    app.run_with_args(&program); //blocks
+}
@@ -0,0 +1,316 @@
+use std::collections::BTreeMap;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use crate::apply_report::{ApplyMethod, ApplyReport};
+
+// Bumped whenever a field is renamed or removed, new fields may appear without it
+pub const OUTPUT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Json,  // one object with "version" and the listed items, for scripts
+    Table, // aligned columns with a header, for people
+    Plain, // one tab separated line per item without a header, for cut and awk
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MonitorRecord {
+    pub id: String, // device name profiles use for this monitor
+    pub rect: RectRecord,
+    pub primary: bool,
+    pub wallpaper: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct RectRecord {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProfileRecord {
+    pub name: String,
+    pub extends: Option<String>,
+    pub fit: Option<String>,
+    pub wallpapers: BTreeMap<String, String>, // own entries only, device -> path as written in the config
+    pub overlays: BTreeMap<String, Vec<String>>, // device -> overlay specs
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduleRecord {
+    pub profile: String,
+    pub hour: u32,
+    pub minute: u32,
+    pub enabled: bool,
+}
+
+// What `status` reports about a running daemon
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DaemonStatusRecord {
+    pub pid: u32,
+    pub config: String,
+    pub profile: Option<String>, // last one the daemon applied
+    pub profiles: usize,
+    pub monitors: usize,
+    pub scheduler_running: bool,
+    pub schedule: Vec<ScheduleRecord>,
+    pub pending_retries: usize, // scheduled applies waiting to retry failed monitors
+    pub uptime_secs: u64,
+}
+
+#[derive(Serialize)]
+struct Listing<'a, T: Serialize + ?Sized> {
+    version: u32,
+    #[serde(flatten)]
+    items: BTreeMap<&'a str, &'a T>,
+}
+
+fn print_json<T: Serialize + ?Sized>(key: &str, items: &T) {
+    let listing = Listing {
+        version: OUTPUT_VERSION,
+        items: BTreeMap::from([(key, items)]),
+    };
+    match serde_json::to_string_pretty(&listing) {
+        Ok(json) => println!("{}", json),
+        Err(e) => eprintln!("Failed to write JSON: {}", e),
+    }
+}
+
+fn print_rows(format: OutputFormat, headers: &[&str], rows: &[Vec<String>]) {
+    print!("{}", format_rows(format, headers, rows));
+}
+
+fn format_rows(format: OutputFormat, headers: &[&str], rows: &[Vec<String>]) -> String {
+    let mut output = String::new();
+    if format == OutputFormat::Plain {
+        for row in rows {
+            output.push_str(&row.join("\t"));
+            output.push('\n');
+        }
+        return output;
+    }
+
+    let mut widths: Vec<usize> = headers.iter().map(|header| header.chars().count()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let mut line = |cells: Vec<&str>| {
+        let padded: Vec<String> = cells.iter().zip(&widths).map(|(cell, width)| format!("{:<1$}", cell, width)).collect();
+        output.push_str(padded.join("  ").trim_end());
+        output.push('\n');
+    };
+    line(headers.to_vec());
+    for row in rows {
+        line(row.iter().map(|cell| cell.as_str()).collect());
+    }
+    output
+}
+
+fn or_dash(value: &Option<String>) -> String {
+    value.clone().unwrap_or_else(|| "-".to_string())
+}
+
+pub fn print_monitors(monitors: &[MonitorRecord], format: OutputFormat) {
+    if format == OutputFormat::Json {
+        return print_json("monitors", monitors);
+    }
+    if monitors.is_empty() && format == OutputFormat::Table {
+        println!("No monitors found.");
+        return;
+    }
+
+    let rows: Vec<Vec<String>> = monitors.iter()
+        .map(|monitor| vec![
+            monitor.id.clone(),
+            format!("{},{}", monitor.rect.x, monitor.rect.y),
+            format!("{}x{}", monitor.rect.width, monitor.rect.height),
+            if monitor.primary { "yes" } else { "no" }.to_string(),
+            or_dash(&monitor.wallpaper),
+        ])
+        .collect();
+    print_rows(format, &["ID", "POSITION", "SIZE", "PRIMARY", "WALLPAPER"], &rows);
+}
+
+pub fn print_profiles(profiles: &[ProfileRecord], format: OutputFormat) {
+    if format == OutputFormat::Json {
+        return print_json("profiles", profiles);
+    }
+    if profiles.is_empty() && format == OutputFormat::Table {
+        println!("No profiles created.");
+        return;
+    }
+
+    let rows: Vec<Vec<String>> = profiles.iter()
+        .map(|profile| vec![
+            profile.name.clone(),
+            or_dash(&profile.extends),
+            or_dash(&profile.fit),
+            profile.wallpapers.len().to_string(),
+        ])
+        .collect();
+    print_rows(format, &["NAME", "EXTENDS", "FIT", "MONITORS"], &rows);
+}
+
+pub fn print_schedule(schedule: &[ScheduleRecord], format: OutputFormat) {
+    if format == OutputFormat::Json {
+        return print_json("schedule", schedule);
+    }
+    if schedule.is_empty() && format == OutputFormat::Table {
+        println!("No scheduled profiles.");
+        return;
+    }
+
+    let rows: Vec<Vec<String>> = schedule.iter()
+        .map(|entry| vec![
+            format!("{:02}:{:02}", entry.hour, entry.minute),
+            entry.profile.clone(),
+            if entry.enabled { "enabled" } else { "disabled" }.to_string(),
+        ])
+        .collect();
+    print_rows(format, &["TIME", "PROFILE", "STATE"], &rows);
+}
+
+// What `apply --dry-run` found, nothing in it has been changed yet
+#[derive(Debug, Clone, Serialize)]
+pub struct ApplyPlanRecord {
+    pub profile: String,
+    pub fit: Option<FitChangeRecord>, // None when the profile leaves the position alone
+    pub monitors: Vec<PlannedWallpaperRecord>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FitChangeRecord {
+    pub current: Option<String>,
+    pub new: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlannedWallpaperRecord {
+    pub device: String, // as written in the profile
+    pub monitor: Option<String>, // monitor the wallpaper would be set on
+    pub exact_match: bool, // false when no monitor matched and the first one would be used
+    pub current: Option<String>,
+    pub new: Option<String>, // file handed to the backend, rendered into the cache for generators and overlays
+    pub changed: bool,
+    pub problem: Option<String>, // why this entry would fail
+}
+
+impl FitChangeRecord {
+    pub fn changed(&self) -> bool {
+        self.current.as_deref() != Some(self.new.as_str())
+    }
+}
+
+pub fn print_apply_plan(plan: &ApplyPlanRecord, format: OutputFormat) {
+    if format == OutputFormat::Json {
+        return print_json("plan", plan);
+    }
+
+    let status = |changed: bool| if changed { "change" } else { "same" }.to_string();
+    let mut rows = Vec::new();
+
+    if let Some(fit) = &plan.fit {
+        rows.push(vec!["fit".to_string(), status(fit.changed()), or_dash(&fit.current), fit.new.clone()]);
+    }
+
+    for entry in &plan.monitors {
+        let mut target = entry.monitor.clone().unwrap_or_else(|| entry.device.clone());
+        if entry.monitor.is_some() && !entry.exact_match {
+            target = format!("{} (no match for {})", target, entry.device);
+        }
+        let status = match &entry.problem {
+            Some(problem) => format!("error: {}", problem),
+            None => status(entry.changed),
+        };
+        rows.push(vec![target, status, or_dash(&entry.current), or_dash(&entry.new)]);
+    }
+
+    if format == OutputFormat::Table {
+        println!("Dry run of profile '{}', nothing was changed:", plan.profile);
+    }
+    print_rows(format, &["TARGET", "STATUS", "CURRENT", "NEW"], &rows);
+}
+
+pub fn print_apply_report(report: &ApplyReport, format: OutputFormat) {
+    if format == OutputFormat::Json {
+        return print_json("report", report);
+    }
+
+    let mut rows = Vec::new();
+    if let Some(fit) = &report.fit {
+        let status = fit.error.as_ref().map(|e| format!("error: {}", e)).unwrap_or_else(|| "ok".to_string());
+        rows.push(vec!["fit".to_string(), status, fit.position.clone()]);
+    }
+    for monitor in &report.monitors {
+        let status = match (&monitor.error, monitor.method) {
+            (Some(e), _) => format!("error: {}", e),
+            (None, Some(ApplyMethod::DeviceName)) => "ok (device name)".to_string(),
+            (None, _) => "ok".to_string(),
+        };
+        let target = monitor.monitor_id.clone().unwrap_or_else(|| monitor.device.clone());
+        rows.push(vec![target, status, monitor.file.clone().unwrap_or_else(|| monitor.path.clone())]);
+    }
+
+    if format == OutputFormat::Table {
+        println!("{}", report.summary());
+        if report.error.is_some() {
+            return;
+        }
+    }
+    print_rows(format, &["TARGET", "STATUS", "FILE"], &rows);
+}
+
+pub fn print_status(status: &DaemonStatusRecord, format: OutputFormat) {
+    if format == OutputFormat::Json {
+        return print_json("status", status);
+    }
+
+    let next = status.schedule.iter()
+        .filter(|entry| entry.enabled)
+        .map(|entry| format!("{:02}:{:02} {}", entry.hour, entry.minute, entry.profile))
+        .collect::<Vec<String>>()
+        .join(", ");
+    let rows = vec![
+        vec!["pid".to_string(), status.pid.to_string()],
+        vec!["config".to_string(), status.config.clone()],
+        vec!["profile".to_string(), or_dash(&status.profile)],
+        vec!["profiles".to_string(), status.profiles.to_string()],
+        vec!["monitors".to_string(), status.monitors.to_string()],
+        vec!["scheduler".to_string(), if status.scheduler_running { "running" } else { "stopped" }.to_string()],
+        vec!["schedule".to_string(), if next.is_empty() { "-".to_string() } else { next }],
+        vec!["retries".to_string(), status.pending_retries.to_string()],
+        vec!["uptime".to_string(), format!("{}s", status.uptime_secs)],
+    ];
+    print_rows(format, &["KEY", "VALUE"], &rows);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows() -> Vec<Vec<String>> {
+        vec![
+            vec!["work".to_string(), "Ünïcode".to_string(), "".to_string()],
+            vec!["a longer name".to_string(), "x".to_string(), "".to_string()],
+        ]
+    }
+
+    #[test]
+    fn tables_pad_columns_by_characters() {
+        assert_eq!(format_rows(OutputFormat::Table, &["NAME", "WALLPAPER", "FIT"], &rows()),
+                   "NAME           WALLPAPER  FIT\n\
+                    work           Ünïcode\n\
+                    a longer name  x\n");
+    }
+
+    #[test]
+    fn plain_rows_are_tab_separated_without_a_header() {
+        assert_eq!(format_rows(OutputFormat::Plain, &["NAME", "WALLPAPER", "FIT"], &rows()),
+                   "work\tÜnïcode\t\na longer name\tx\t\n");
+    }
+}
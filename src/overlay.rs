@@ -0,0 +1,456 @@
+use std::fmt::Write;
+use std::path::Path;
+use ab_glyph::{point, Font, FontVec, PxScale, ScaleFont};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{Datelike, Local, NaiveDate};
+use image::{Rgba, RgbaImage};
+
+// Fonts tried in order when an overlay does not name one explicitly
+const DEFAULT_FONTS: &[&str] = &[
+    "C:\\Windows\\Fonts\\consola.ttf",
+    "C:\\Windows\\Fonts\\arial.ttf",
+    "/usr/share/fonts/truetype/dejavu/DejaVuSansMono.ttf",
+    "/usr/share/fonts/TTF/DejaVuSansMono.ttf",
+    "/usr/share/fonts/dejavu-sans-mono-fonts/DejaVuSansMono.ttf",
+];
+
+// Used when none of the default fonts is installed, see assets/DejaVuSansMono-LICENSE.txt
+const FALLBACK_FONT: &[u8] = include_bytes!("../assets/DejaVuSansMono.ttf");
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OverlayKind {
+    Hostname,
+    Date(String),  // chrono format string
+    Quote(String), // file with one quote per line
+    Calendar,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverlayPosition {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    Center,
+}
+
+impl OverlayPosition {
+    fn to_string(self) -> &'static str {
+        match self {
+            Self::TopLeft => "top-left",
+            Self::TopRight => "top-right",
+            Self::BottomLeft => "bottom-left",
+            Self::BottomRight => "bottom-right",
+            Self::Center => "center",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "top-left" => Some(Self::TopLeft),
+            "top-right" => Some(Self::TopRight),
+            "bottom-left" => Some(Self::BottomLeft),
+            "bottom-right" => Some(Self::BottomRight),
+            "center" => Some(Self::Center),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Overlay {
+    pub kind: OverlayKind,
+    pub font: Option<String>,
+    pub size: f32,
+    pub position: OverlayPosition,
+    pub color: [u8; 4],
+    pub margin: u32,
+}
+
+impl Overlay {
+    pub fn new(kind: OverlayKind) -> Self {
+        Self {
+            kind,
+            font: None,
+            size: 32.0,
+            position: OverlayPosition::BottomRight,
+            color: [255, 255, 255, 255],
+            margin: 24,
+        }
+    }
+
+    // Parses specs like "hostname;size=48;position=top-left;color=#ffcc00"
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let parts = split_spec(spec);
+        let mut parts = parts.iter().map(|part| part.trim());
+
+        let kind_part = parts.next().unwrap_or_default();
+        let (kind_name, kind_arg) = match kind_part.split_once(':') {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (kind_part, None),
+        };
+
+        let kind = match (kind_name, kind_arg) {
+            ("hostname", None) => OverlayKind::Hostname,
+            ("date", format) => {
+                let format = format.unwrap_or("%Y-%m-%d");
+                if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+                    return Err(format!("Invalid date format: {}", format));
+                }
+                OverlayKind::Date(format.to_string())
+            }
+            ("quote", Some(file)) => OverlayKind::Quote(file.to_string()),
+            ("calendar", None) => OverlayKind::Calendar,
+            _ => return Err(format!("Unknown overlay kind: {}", kind_part)),
+        };
+
+        let mut overlay = Overlay::new(kind);
+
+        for part in parts {
+            if part.is_empty() {
+                continue;
+            }
+
+            let (key, value) = part.split_once('=')
+                .ok_or_else(|| format!("Expected key=value in overlay option: {}", part))?;
+
+            match key {
+                "font" => overlay.font = Some(value.to_string()),
+                "size" => {
+                    overlay.size = value.parse::<f32>()
+                        .ok()
+                        .filter(|size| *size > 0.0)
+                        .ok_or_else(|| format!("Invalid overlay size: {}", value))?;
+                }
+                "position" => {
+                    overlay.position = OverlayPosition::parse(value)
+                        .ok_or_else(|| format!("Invalid overlay position: {}", value))?;
+                }
+                "color" => overlay.color = parse_color(value)?,
+                "margin" => {
+                    overlay.margin = value.parse::<u32>()
+                        .map_err(|_| format!("Invalid overlay margin: {}", value))?;
+                }
+                _ => return Err(format!("Unknown overlay option: {}", key)),
+            }
+        }
+
+        Ok(overlay)
+    }
+
+    pub fn to_spec(&self) -> String {
+        let mut spec = match &self.kind {
+            OverlayKind::Hostname => "hostname".to_string(),
+            OverlayKind::Date(format) => format!("date:{}", escape_spec(format)),
+            OverlayKind::Quote(file) => format!("quote:{}", escape_spec(file)),
+            OverlayKind::Calendar => "calendar".to_string(),
+        };
+
+        if let Some(font) = &self.font {
+            spec.push_str(&format!(";font={}", escape_spec(font)));
+        }
+        spec.push_str(&format!(";size={};position={};color={};margin={}",
+                               self.size,
+                               self.position.to_string(),
+                               format_color(self.color),
+                               self.margin));
+        spec
+    }
+
+    fn text_lines(&self) -> Vec<String> {
+        match &self.kind {
+            OverlayKind::Hostname => vec![hostname()],
+            OverlayKind::Date(format) => {
+                // Formats from specs are checked by parse, chrono reports the rest as a fmt::Error
+                let mut text = String::new();
+                if write!(text, "{}", Local::now().format(format)).is_err() {
+                    text = format.clone();
+                }
+                vec![text]
+            }
+            OverlayKind::Quote(file) => vec![quote_of_the_day(file)],
+            OverlayKind::Calendar => calendar_lines(Local::now().date_naive()),
+        }
+    }
+}
+
+// Options are separated by ';', a ';' inside a file name or date format is doubled
+fn split_spec(spec: &str) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = spec.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != ';' {
+            parts.last_mut().unwrap().push(c);
+        } else if chars.next_if_eq(&';').is_some() {
+            parts.last_mut().unwrap().push(';');
+        } else {
+            parts.push(String::new());
+        }
+    }
+    parts
+}
+
+fn escape_spec(value: &str) -> String {
+    value.replace(';', ";;")
+}
+
+pub fn parse_color(value: &str) -> Result<[u8; 4], String> {
+    let hex = value.strip_prefix('#').unwrap_or(value);
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16);
+
+    if !hex.is_ascii() {
+        return Err(format!("Invalid color: {}", value));
+    }
+
+    let parsed = match hex.len() {
+        6 => (channel(0), channel(2), channel(4), Ok(255)),
+        8 => (channel(0), channel(2), channel(4), channel(6)),
+        _ => return Err(format!("Invalid color: {}", value)),
+    };
+
+    match parsed {
+        (Ok(r), Ok(g), Ok(b), Ok(a)) => Ok([r, g, b, a]),
+        _ => Err(format!("Invalid color: {}", value)),
+    }
+}
+
+pub fn format_color(color: [u8; 4]) -> String {
+    if color[3] == 255 {
+        format!("#{:02x}{:02x}{:02x}", color[0], color[1], color[2])
+    } else {
+        format!("#{:02x}{:02x}{:02x}{:02x}", color[0], color[1], color[2], color[3])
+    }
+}
+
+fn hostname() -> String {
+    std::env::var("COMPUTERNAME")
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .or_else(|_| std::fs::read_to_string("/etc/hostname"))
+        .map(|name| name.trim().to_string())
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+// Picks a stable line for the current day so the quote does not change on every apply
+fn quote_of_the_day(file: &str) -> String {
+    let quotes: Vec<String> = std::fs::read_to_string(file)
+        .unwrap_or_default()
+        .lines()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect();
+
+    if quotes.is_empty() {
+        return String::new();
+    }
+
+    let day = Local::now().ordinal0() as usize;
+    quotes[day % quotes.len()].clone()
+}
+
+fn calendar_lines(today: NaiveDate) -> Vec<String> {
+    let first = today.with_day(1).unwrap_or(today);
+    let mut lines = vec![
+        format!("{:^20}", first.format("%B %Y").to_string()),
+        "Mo Tu We Th Fr Sa Su".to_string(),
+    ];
+
+    let mut week = "   ".repeat(first.weekday().num_days_from_monday() as usize);
+    let mut day = first;
+    while day.month() == first.month() {
+        if day == today {
+            week.push_str(&format!("{:>2}*", day.day()));
+        } else {
+            week.push_str(&format!("{:>2} ", day.day()));
+        }
+
+        if day.weekday().num_days_from_monday() == 6 {
+            lines.push(week.trim_end().to_string());
+            week = String::new();
+        }
+
+        day = match day.succ_opt() {
+            Some(next) => next,
+            None => break,
+        };
+    }
+
+    if !week.trim().is_empty() {
+        lines.push(week.trim_end().to_string());
+    }
+
+    lines
+}
+
+fn load_font(font: &Option<String>) -> Result<FontVec, String> {
+    let candidates: Vec<&str> = match font {
+        Some(path) => vec![path.as_str()],
+        None => DEFAULT_FONTS.to_vec(),
+    };
+
+    for candidate in &candidates {
+        if let Ok(data) = std::fs::read(candidate) {
+            return FontVec::try_from_vec(data)
+                .map_err(|_| format!("Invalid font file: {}", candidate));
+        }
+    }
+
+    match font {
+        Some(path) => Err(format!("Font file not found: {}", path)),
+        None => FontVec::try_from_vec(FALLBACK_FONT.to_vec()).map_err(|_| "Invalid built-in font".to_string()),
+    }
+}
+
+fn blend(pixel: &mut Rgba<u8>, color: [u8; 4], coverage: f32) {
+    let alpha = coverage.clamp(0.0, 1.0) * color[3] as f32 / 255.0;
+    for (channel, value) in pixel.0.iter_mut().zip(color).take(3) {
+        let blended = *channel as f32 * (1.0 - alpha) + value as f32 * alpha;
+        *channel = blended.round() as u8;
+    }
+    pixel.0[3] = pixel.0[3].max((alpha * 255.0).round() as u8);
+}
+
+fn line_width(font: &FontVec, scale: PxScale, text: &str) -> f32 {
+    let scaled = font.as_scaled(scale);
+    let mut width = 0.0;
+    let mut last = None;
+
+    for c in text.chars() {
+        let id = scaled.glyph_id(c);
+        if let Some(prev) = last {
+            width += scaled.kern(prev, id);
+        }
+        width += scaled.h_advance(id);
+        last = Some(id);
+    }
+
+    width
+}
+
+fn draw_line(image: &mut RgbaImage, font: &FontVec, scale: PxScale, x: f32, baseline: f32, text: &str, color: [u8; 4]) {
+    let scaled = font.as_scaled(scale);
+    let mut caret = x;
+    let mut last = None;
+
+    for c in text.chars() {
+        let id = scaled.glyph_id(c);
+        if let Some(prev) = last {
+            caret += scaled.kern(prev, id);
+        }
+
+        let glyph = id.with_scale_and_position(scale, point(caret, baseline));
+        caret += scaled.h_advance(id);
+        last = Some(id);
+
+        if let Some(outlined) = font.outline_glyph(glyph) {
+            let bounds = outlined.px_bounds();
+            outlined.draw(|gx, gy, coverage| {
+                let px = bounds.min.x as i64 + gx as i64;
+                let py = bounds.min.y as i64 + gy as i64;
+                if px >= 0 && py >= 0 && (px as u32) < image.width() && (py as u32) < image.height() {
+                    blend(image.get_pixel_mut(px as u32, py as u32), color, coverage);
+                }
+            });
+        }
+    }
+}
+
+pub fn draw_overlay(image: &mut RgbaImage, overlay: &Overlay) -> Result<(), String> {
+    let lines = overlay.text_lines();
+    if lines.iter().all(|line| line.is_empty()) {
+        return Ok(());
+    }
+
+    let font = load_font(&overlay.font)?;
+    let scale = PxScale::from(overlay.size);
+    let scaled = font.as_scaled(scale);
+    let line_height = scaled.height() + scaled.line_gap();
+
+    let block_width = lines.iter()
+        .map(|line| line_width(&font, scale, line))
+        .fold(0.0, f32::max);
+    let block_height = line_height * lines.len() as f32;
+
+    let margin = overlay.margin as f32;
+    let (width, height) = (image.width() as f32, image.height() as f32);
+    let (x, y) = match overlay.position {
+        OverlayPosition::TopLeft => (margin, margin),
+        OverlayPosition::TopRight => (width - block_width - margin, margin),
+        OverlayPosition::BottomLeft => (margin, height - block_height - margin),
+        OverlayPosition::BottomRight => (width - block_width - margin, height - block_height - margin),
+        OverlayPosition::Center => ((width - block_width) / 2.0, (height - block_height) / 2.0),
+    };
+
+    for (i, line) in lines.iter().enumerate() {
+        let baseline = y + scaled.ascent() + line_height * i as f32;
+        draw_line(image, &font, scale, x, baseline, line, overlay.color);
+    }
+
+    Ok(())
+}
+
+// Renders all overlays onto a copy of `source` and writes the result as PNG to `output`
+pub fn render_overlays(source: &str, overlays: &[Overlay], output: &Path) -> Result<(), String> {
+    let mut image = image::open(source)
+        .map_err(|e| format!("Failed to open {}: {}", source, e))?
+        .to_rgba8();
+
+    for overlay in overlays {
+        draw_overlay(&mut image, overlay)?;
+    }
+
+    if let Some(parent) = output.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+
+    image.save_with_format(output, image::ImageFormat::Png)
+        .map_err(|e| format!("Failed to write {}: {}", output.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fallback_font_is_valid() {
+        assert!(FontVec::try_from_vec(FALLBACK_FONT.to_vec()).is_ok());
+    }
+
+    #[test]
+    fn missing_explicit_font_is_an_error() {
+        assert!(load_font(&Some("/nonexistent/font.ttf".to_string())).is_err());
+    }
+
+    #[test]
+    fn invalid_date_formats_are_rejected() {
+        assert_eq!(Overlay::parse("date:%Q").unwrap_err(), "Invalid date format: %Q");
+        assert!(Overlay::parse("date:%A %d.%m.").is_ok());
+    }
+
+    #[test]
+    fn specs_with_semicolons_round_trip() {
+        let mut overlay = Overlay::new(OverlayKind::Quote("C:\\quotes;old.txt".to_string()));
+        overlay.font = Some("/fonts/a;b.ttf".to_string());
+        assert_eq!(Overlay::parse(&overlay.to_spec()).unwrap(), overlay);
+
+        let overlay = Overlay::new(OverlayKind::Date("%H;%M;".to_string()));
+        assert_eq!(Overlay::parse(&overlay.to_spec()).unwrap(), overlay);
+    }
+
+    #[test]
+    fn renders_text_onto_the_image() {
+        let dir = std::env::temp_dir().join(format!("wallpaper-helper-overlay-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("source.png");
+        let output = dir.join("output.png");
+        RgbaImage::from_pixel(320, 200, Rgba([0, 0, 0, 255])).save(&source).unwrap();
+
+        let overlay = Overlay::parse("date:%Y-%m-%d;size=40;position=center;color=#ffffff").unwrap();
+        render_overlays(source.to_str().unwrap(), &[overlay], &output).unwrap();
+
+        let rendered = image::open(&output).unwrap().to_rgba8();
+        assert_eq!(rendered.dimensions(), (320, 200));
+        assert!(rendered.pixels().any(|pixel| pixel.0[0] > 128));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
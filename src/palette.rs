@@ -0,0 +1,315 @@
+use std::path::Path;
+use image::imageops::FilterType;
+
+pub type Color = [u8; 3];
+
+// Built-in templates written next to the cache on every export, user templates come on top
+const BUILTIN_TEMPLATES: &[(&str, &str)] = &[
+    ("colors.Xresources", "*background: {background}
+*foreground: {foreground}
+*cursorColor: {cursor}
+*color0: {color0}
+*color1: {color1}
+*color2: {color2}
+*color3: {color3}
+*color4: {color4}
+*color5: {color5}
+*color6: {color6}
+*color7: {color7}
+*color8: {color8}
+*color9: {color9}
+*color10: {color10}
+*color11: {color11}
+*color12: {color12}
+*color13: {color13}
+*color14: {color14}
+*color15: {color15}
+"),
+    ("colors-kitty.conf", "background {background}
+foreground {foreground}
+cursor {cursor}
+color0 {color0}
+color1 {color1}
+color2 {color2}
+color3 {color3}
+color4 {color4}
+color5 {color5}
+color6 {color6}
+color7 {color7}
+color8 {color8}
+color9 {color9}
+color10 {color10}
+color11 {color11}
+color12 {color12}
+color13 {color13}
+color14 {color14}
+color15 {color15}
+"),
+    ("colors-alacritty.toml", "[colors.primary]
+background = \"{background}\"
+foreground = \"{foreground}\"
+
+[colors.cursor]
+cursor = \"{cursor}\"
+
+[colors.normal]
+black = \"{color0}\"
+red = \"{color1}\"
+green = \"{color2}\"
+yellow = \"{color3}\"
+blue = \"{color4}\"
+magenta = \"{color5}\"
+cyan = \"{color6}\"
+white = \"{color7}\"
+
+[colors.bright]
+black = \"{color8}\"
+red = \"{color9}\"
+green = \"{color10}\"
+yellow = \"{color11}\"
+blue = \"{color12}\"
+magenta = \"{color13}\"
+cyan = \"{color14}\"
+white = \"{color15}\"
+"),
+    ("colors.css", ":root {
+  --wallpaper: url(\"{wallpaper.css}\");
+  --background: {background};
+  --foreground: {foreground};
+  --cursor: {cursor};
+  --color0: {color0};
+  --color1: {color1};
+  --color2: {color2};
+  --color3: {color3};
+  --color4: {color4};
+  --color5: {color5};
+  --color6: {color6};
+  --color7: {color7};
+  --color8: {color8};
+  --color9: {color9};
+  --color10: {color10};
+  --color11: {color11};
+  --color12: {color12};
+  --color13: {color13};
+  --color14: {color14};
+  --color15: {color15};
+}
+"),
+    ("colors.json", "{
+  \"wallpaper\": \"{wallpaper.json}\",
+  \"special\": {
+    \"background\": \"{background}\",
+    \"foreground\": \"{foreground}\",
+    \"cursor\": \"{cursor}\"
+  },
+  \"colors\": {
+    \"color0\": \"{color0}\",
+    \"color1\": \"{color1}\",
+    \"color2\": \"{color2}\",
+    \"color3\": \"{color3}\",
+    \"color4\": \"{color4}\",
+    \"color5\": \"{color5}\",
+    \"color6\": \"{color6}\",
+    \"color7\": \"{color7}\",
+    \"color8\": \"{color8}\",
+    \"color9\": \"{color9}\",
+    \"color10\": \"{color10}\",
+    \"color11\": \"{color11}\",
+    \"color12\": \"{color12}\",
+    \"color13\": \"{color13}\",
+    \"color14\": \"{color14}\",
+    \"color15\": \"{color15}\"
+  }
+}
+"),
+];
+
+#[derive(Debug, Clone)]
+pub struct Theme {
+    pub wallpaper: String,
+    pub colors: Vec<Color>, // color0..color15
+    pub background: Color,
+    pub foreground: Color,
+    pub cursor: Color,
+}
+
+fn luminance(color: &Color) -> f32 {
+    0.2126 * color[0] as f32 + 0.7152 * color[1] as f32 + 0.0722 * color[2] as f32
+}
+
+fn lighten(color: Color, amount: f32) -> Color {
+    color.map(|channel| (channel as f32 + (255.0 - channel as f32) * amount).round() as u8)
+}
+
+fn darken(color: Color, amount: f32) -> Color {
+    color.map(|channel| (channel as f32 * (1.0 - amount)).round() as u8)
+}
+
+fn to_hex(color: &Color) -> String {
+    format!("#{:02x}{:02x}{:02x}", color[0], color[1], color[2])
+}
+
+// Splits the box along its widest channel at the median until `count` boxes exist
+fn median_cut(pixels: Vec<Color>, count: usize) -> Vec<Color> {
+    let mut boxes: Vec<Vec<Color>> = vec![pixels];
+
+    while boxes.len() < count {
+        let widest = boxes.iter()
+            .enumerate()
+            .filter(|(_, pixels)| pixels.len() > 1)
+            .map(|(i, pixels)| (i, widest_channel(pixels)))
+            .max_by_key(|(_, (_, range))| *range);
+
+        let (index, channel) = match widest {
+            Some((index, (channel, range))) if range > 0 => (index, channel),
+            _ => break,
+        };
+
+        let mut pixels = boxes.swap_remove(index);
+        pixels.sort_unstable_by_key(|pixel| pixel[channel]);
+        let upper = pixels.split_off(pixels.len() / 2);
+        boxes.push(pixels);
+        boxes.push(upper);
+    }
+
+    boxes.iter()
+        .filter(|pixels| !pixels.is_empty())
+        .map(|pixels| {
+            let mut sum = [0u64; 3];
+            for pixel in pixels {
+                for (total, channel) in sum.iter_mut().zip(pixel) {
+                    *total += *channel as u64;
+                }
+            }
+            sum.map(|total| (total / pixels.len() as u64) as u8)
+        })
+        .collect()
+}
+
+fn widest_channel(pixels: &[Color]) -> (usize, u8) {
+    (0..3)
+        .map(|channel| {
+            let min = pixels.iter().map(|pixel| pixel[channel]).min().unwrap_or(0);
+            let max = pixels.iter().map(|pixel| pixel[channel]).max().unwrap_or(0);
+            (channel, max - min)
+        })
+        .max_by_key(|(_, range)| *range)
+        .unwrap_or((0, 0))
+}
+
+pub fn extract_palette(wallpaper_paths: &[String], count: usize) -> Result<Vec<Color>, String> {
+    let mut pixels = Vec::new();
+
+    for path in wallpaper_paths {
+        let image = image::open(path)
+            .map_err(|e| format!("Failed to open {}: {}", path, e))?
+            .resize(128, 128, FilterType::Triangle)
+            .to_rgb8();
+        pixels.extend(image.pixels().map(|pixel| pixel.0));
+    }
+
+    if pixels.is_empty() {
+        return Err("No images to extract a palette from".to_string());
+    }
+
+    let mut palette = median_cut(pixels, count);
+    palette.sort_by(|a, b| luminance(a).total_cmp(&luminance(b)));
+    Ok(palette)
+}
+
+impl Theme {
+    // Builds a 16 color terminal scheme from the extracted palette, darkest color as background
+    pub fn from_palette(wallpaper: &str, palette: &[Color]) -> Self {
+        let darkest = palette.first().copied().unwrap_or([0, 0, 0]);
+        let lightest = palette.last().copied().unwrap_or([255, 255, 255]);
+        let background = darken(darkest, 0.4);
+        let foreground = lighten(lightest, 0.6);
+
+        let mut colors = vec![background];
+        for i in 1..7 {
+            colors.push(palette.get(i).copied().unwrap_or(lightest));
+        }
+        colors.push(foreground);
+
+        colors.push(lighten(background, 0.25));
+        for i in 1..7 {
+            colors.push(lighten(colors[i], 0.2));
+        }
+        colors.push(foreground);
+
+        Self {
+            wallpaper: wallpaper.to_string(),
+            colors,
+            background,
+            foreground,
+            cursor: foreground,
+        }
+    }
+
+    // Replaces {colorN}, {background}, {foreground}, {cursor} and {wallpaper} placeholders;
+    // ".strip" gives the hex value without '#', ".rgb" gives "r,g,b". {wallpaper.json} and
+    // {wallpaper.css} are escaped for a double quoted string in those languages.
+    pub fn render_template(&self, template: &str) -> String {
+        let mut named: Vec<(String, Color)> = vec![
+            ("background".to_string(), self.background),
+            ("foreground".to_string(), self.foreground),
+            ("cursor".to_string(), self.cursor),
+        ];
+        for (i, color) in self.colors.iter().enumerate() {
+            named.push((format!("color{}", i), *color));
+        }
+
+        let mut output = template
+            .replace("{wallpaper.json}", &self.wallpaper.replace('\\', "\\\\").replace('"', "\\\""))
+            .replace("{wallpaper.css}", &self.wallpaper.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\a "))
+            .replace("{wallpaper}", &self.wallpaper);
+        for (name, color) in &named {
+            output = output
+                .replace(&format!("{{{}.strip}}", name), &to_hex(color)[1..])
+                .replace(&format!("{{{}.rgb}}", name), &format!("{},{},{}", color[0], color[1], color[2]))
+                .replace(&format!("{{{}}}", name), &to_hex(color));
+        }
+        output
+    }
+
+    pub fn export(&self, output_dir: &Path, user_templates: &[(String, String)]) -> Result<Vec<String>, String> {
+        std::fs::create_dir_all(output_dir)
+            .map_err(|e| format!("Failed to create {}: {}", output_dir.display(), e))?;
+
+        let mut written = Vec::new();
+
+        for (file_name, template) in BUILTIN_TEMPLATES {
+            let output = output_dir.join(file_name);
+            std::fs::write(&output, self.render_template(template))
+                .map_err(|e| format!("Failed to write {}: {}", output.display(), e))?;
+            written.push(output.to_string_lossy().to_string());
+        }
+
+        for (template_path, output_path) in user_templates {
+            let template = std::fs::read_to_string(template_path)
+                .map_err(|e| format!("Failed to read template {}: {}", template_path, e))?;
+            std::fs::write(output_path, self.render_template(&template))
+                .map_err(|e| format!("Failed to write {}: {}", output_path, e))?;
+            written.push(output_path.clone());
+        }
+
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn placeholders_are_replaced() {
+        let theme = Theme::from_palette("C:\\walls\\\"a\".png", &[[0, 0, 0], [255, 0, 0], [250, 250, 250]]);
+        assert_eq!(theme.colors.len(), 16);
+        assert_eq!(theme.render_template("{color1} {color1.strip} {color1.rgb}"), "#ff0000 ff0000 255,0,0");
+        assert_eq!(theme.render_template("{background} {color9}"), "#000000 #ff3333");
+        assert_eq!(theme.render_template("{wallpaper}"), "C:\\walls\\\"a\".png");
+        assert_eq!(theme.render_template("\"{wallpaper.json}\""), "\"C:\\\\walls\\\\\\\"a\\\".png\"");
+        assert_eq!(theme.render_template("url(\"{wallpaper.css}\")"), "url(\"C:\\\\walls\\\\\\\"a\\\".png\")");
+        assert_eq!(theme.render_template("{unknown} {color}"), "{unknown} {color}");
+    }
+}
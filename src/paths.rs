@@ -0,0 +1,157 @@
+use std::path::{Component, Path, PathBuf};
+use crate::generator::is_generator_spec;
+
+// Profiles may store "~/...", "$VAR/..." or relative paths so one config works on several
+// machines. They are only expanded when a wallpaper is used, never when the config is saved.
+#[derive(Debug, Clone, Default)]
+pub struct PathContext {
+    pub wallpaper_root: Option<String>, // unexpanded, relative roots are relative to the config directory
+    pub config_dir: PathBuf,
+}
+
+impl PathContext {
+    pub fn new(wallpaper_root: Option<String>, config_file: &str) -> Self {
+        let config_dir = match Path::new(config_file).parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+
+        Self {
+            wallpaper_root: wallpaper_root.filter(|root| !root.is_empty()),
+            config_dir,
+        }
+    }
+
+    // Relative wallpaper paths start here: the wallpaper root if set, otherwise the config directory
+    pub fn base_dir(&self) -> Result<PathBuf, String> {
+        match &self.wallpaper_root {
+            Some(root) => {
+                let root = PathBuf::from(expand_vars(root)?);
+                Ok(if root.is_absolute() { root } else { self.config_dir.join(root) })
+            }
+            None => Ok(self.config_dir.clone()),
+        }
+    }
+
+    pub fn expand(&self, path: &str) -> Result<String, String> {
+        if is_generator_spec(path) {
+            return Ok(path.to_string());
+        }
+
+        let expanded = PathBuf::from(expand_vars(path)?);
+        if expanded.is_absolute() {
+            Ok(expanded.to_string_lossy().to_string())
+        } else {
+            Ok(self.base_dir()?.join(expanded).to_string_lossy().to_string())
+        }
+    }
+
+    // "/home/me/Pictures/walls/a.png" -> "a.png" when the root is "~/Pictures/walls".
+    // Uses forward slashes, which Windows accepts as well, so the result is portable.
+    pub fn relative_to_root(&self, path: &str) -> Option<String> {
+        self.wallpaper_root.as_ref()?;
+        let absolute = |path: &Path| path.canonicalize().unwrap_or_else(|_| normalize(path));
+        let base = absolute(&self.base_dir().ok()?);
+        let path = absolute(Path::new(path));
+
+        let relative = path.strip_prefix(&base).ok()?;
+        let parts: Vec<String> = relative.components()
+            .map(|component| component.as_os_str().to_string_lossy().to_string())
+            .collect();
+        if parts.is_empty() {
+            None
+        } else {
+            Some(parts.join("/"))
+        }
+    }
+}
+
+pub fn home_dir() -> Option<PathBuf> {
+    ["HOME", "USERPROFILE"].iter()
+        .filter_map(std::env::var_os)
+        .find(|value| !value.is_empty())
+        .map(PathBuf::from)
+}
+
+// Expands a leading "~" and every $NAME or ${NAME}; unset variables are an error
+// instead of silently turning "$PICS/a.png" into "/a.png"
+pub fn expand_vars(path: &str) -> Result<String, String> {
+    let mut expanded = String::new();
+    let mut rest = path;
+
+    if rest == "~" || rest.starts_with("~/") || rest.starts_with("~\\") {
+        let home = home_dir().ok_or_else(|| "cannot expand '~', the home directory is unknown".to_string())?;
+        expanded.push_str(&home.to_string_lossy());
+        rest = &rest[1..];
+    }
+
+    while let Some(pos) = rest.find('$') {
+        expanded.push_str(&rest[..pos]);
+        let after = &rest[pos + 1..];
+
+        let (name, remainder) = if let Some(braced) = after.strip_prefix('{') {
+            match braced.find('}') {
+                Some(end) => (&braced[..end], &braced[end + 1..]),
+                None => return Err(format!("unterminated '${{' in {}", path)),
+            }
+        } else {
+            let end = after.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(after.len());
+            (&after[..end], &after[end..])
+        };
+
+        if name.is_empty() {
+            // A lone '$' is just part of the file name
+            expanded.push('$');
+        } else {
+            let value = std::env::var(name).map_err(|_| format!("environment variable ${} is not set", name))?;
+            expanded.push_str(&value);
+        }
+        rest = remainder;
+    }
+
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+// Resolves "." and ".." without touching the file system, so missing files can still be compared
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            _ => normalized.push(component.as_os_str()),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_paths_start_at_the_wallpaper_root_or_the_config() {
+        let config_only = PathContext::new(None, "/etc/wallpapers/config.toml");
+        assert_eq!(config_only.expand("a.png").unwrap(), Path::new("/etc/wallpapers").join("a.png").to_string_lossy());
+
+        let with_root = PathContext::new(Some("walls".to_string()), "/etc/wallpapers/config.toml");
+        assert_eq!(with_root.expand("a.png").unwrap(), Path::new("/etc/wallpapers/walls").join("a.png").to_string_lossy());
+
+        let absolute = std::env::temp_dir().join("a.png").to_string_lossy().to_string();
+        assert_eq!(with_root.expand(&absolute).unwrap(), absolute);
+        assert_eq!(with_root.expand("generate:solid:#000000").unwrap(), "generate:solid:#000000");
+    }
+
+    #[test]
+    fn variables_are_expanded() {
+        std::env::set_var("WALLPAPER_HELPER_TEST_PICS", "/pics");
+        let context = PathContext::default();
+        assert_eq!(context.expand("$WALLPAPER_HELPER_TEST_PICS/a.png").unwrap(), "/pics/a.png");
+        assert_eq!(context.expand("${WALLPAPER_HELPER_TEST_PICS}/a$.png").unwrap(), "/pics/a$.png");
+        assert!(context.expand("$WALLPAPER_HELPER_TEST_UNSET/a.png").unwrap_err().contains("is not set"));
+        assert!(context.expand("${WALLPAPER_HELPER_TEST_PICS/a.png").is_err());
+    }
+}
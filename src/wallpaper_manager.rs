@@ -1,25 +1,48 @@
 use std::collections::HashMap;
 use std::ffi::{CString, OsStr};
-use std::fs::File;
-use std::io::{BufRead, BufReader, Write};
 use std::ops::Index;
 use std::os::windows::ffi::OsStrExt;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use chrono::{Local, Timelike};
+use log::{debug, error, info, warn};
 use winapi::um::winuser::MONITORINFOF_PRIMARY;
 use windows::core::{BOOL, GUID, HRESULT, HSTRING, Result, PWSTR};
 
-use windows::Win32::UI::Shell::{IDesktopWallpaper, DesktopWallpaper};
+use windows::Win32::UI::Shell::{IDesktopWallpaper, DesktopWallpaper, DESKTOP_WALLPAPER_POSITION};
 use windows::Win32::Graphics::Gdi::{EnumDisplayMonitors, GetMonitorInfoW, HDC, HMONITOR, MONITORINFOEXW};
 use windows::Win32::Foundation::{RECT, LPARAM, FALSE, TRUE};
 use windows::Win32::System::Com::{CoCreateInstance, CoInitialize, CoTaskMemFree, CoUninitialize, CLSCTX_ALL};
+use crate::apply_report::{ApplyMethod, ApplyReport, FitApplyResult, MonitorApplyResult, PendingRetry};
+use crate::bundle::{archive_name, extract_image, is_archive_image, open_bundle, remap_devices, write_bundle,
+                    BundleManifest, BundleMonitor, BUNDLE_VERSION};
+use crate::config::{cache_dir, check_config_file, is_legacy_config, legacy_config_for, preserve_formatting, serialize_config, serialize_profiles, unspanned,
+                    write_atomically, ConfigFile, LoadedConfig, ScheduleConfig, TemplateConfig, CONFIG_VERSION,
+                    DEFAULT_CONFIG_BACKUPS};
+use crate::config_watcher::WatchPath;
+use crate::diagnostics::{has_errors, Severity};
+use crate::duplicates::{find_duplicate_clusters, images_in_folder, DuplicateCluster};
+use crate::error::{WallpaperError, WallpaperResult};
+use crate::fit::{check_fit, fit_score, FitWarning};
+use crate::generator::{is_generator_spec, render_generator, GeneratorSpec};
+use crate::hooks::{hook_environment, run_hooks, HookStage, HookThreads, Hooks};
+use crate::includes::{profiles_for_file, ConfigSources, DROP_IN_DIR};
+use crate::inheritance::{parent_chain, resolve_profile, ResolvedProfile};
+use crate::legacy_format::write_legacy_config;
+use crate::output::{ApplyPlanRecord, FitChangeRecord, MonitorRecord, PlannedWallpaperRecord, ProfileRecord,
+                    RectRecord, ScheduleRecord};
+use crate::overlay::{draw_overlay, render_overlays, Overlay};
+use crate::palette::{extract_palette, Theme};
+use crate::paths::{expand_vars, PathContext};
+
+// Width of the previews in the window, the height follows the monitor
+const PREVIEW_WIDTH: u32 = 480;
 
 // Desktop wallpaper position constants
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 #[repr(i32)]
 pub enum DesktopWallpaperPosition {
     Center = 0,
@@ -31,7 +54,7 @@ pub enum DesktopWallpaperPosition {
 }
 
 impl DesktopWallpaperPosition {
-    fn to_string(&self) -> &'static str {
+    pub fn to_string(&self) -> &'static str {
         match self {
             Self::Center => "Center",
             Self::Tile => "Tile",
@@ -41,6 +64,24 @@ impl DesktopWallpaperPosition {
             Self::Span => "Span",
         }
     }
+
+    pub fn from_value(value: i32) -> Option<Self> {
+        [Self::Center, Self::Tile, Self::Stretch, Self::Fit, Self::Fill, Self::Span]
+            .into_iter()
+            .find(|position| *position as i32 == value)
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "center" => Some(Self::Center),
+            "tile" => Some(Self::Tile),
+            "stretch" => Some(Self::Stretch),
+            "fit" => Some(Self::Fit),
+            "fill" => Some(Self::Fill),
+            "span" => Some(Self::Span),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -66,7 +107,24 @@ impl std::fmt::Debug for MonitorInfo {
 #[derive(Debug, Clone)]
 pub struct WallpaperProfile {
     pub name: String,
+    pub extends: Option<String>, // parent profile, its entries apply unless overridden here
     pub monitor_wallpapers: HashMap<String, String>, // deviceName -> wallpaperPath
+    pub overlays: HashMap<String, Vec<Overlay>>, // deviceName -> overlays drawn before applying
+    pub fit: Option<DesktopWallpaperPosition>,
+    pub hooks: Hooks,
+}
+
+impl WallpaperProfile {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            extends: None,
+            monitor_wallpapers: HashMap::new(),
+            overlays: HashMap::new(),
+            fit: None,
+            hooks: Hooks::default(),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -83,6 +141,98 @@ pub struct WallpaperManager {
     pub profiles: HashMap<String, WallpaperProfile>,
     pub schedule: Vec<ScheduleEntry>,
     pub scheduler_running: Arc<AtomicBool>,
+    pub due_profiles: Arc<Mutex<Vec<String>>>, // profiles the scheduler thread wants applied
+    pub scheduler_minute: Arc<Mutex<Option<(u32, u32)>>>, // last minute the scheduler checked, kept across reloads
+    pub pending_retries: Vec<PendingRetry>, // scheduled applies with monitors that failed
+    pub cache_dir: PathBuf,
+    pub theme_templates: Vec<(String, String)>, // templatePath -> outputPath
+    pub hooks: Hooks, // global hooks, run before the applied profile's own
+    pub hook_threads: HookThreads, // post_apply hooks still running
+    pub config_document: ConfigFile, // last loaded TOML, keeps unknown keys for the next save
+    pub config_backups: usize,
+    pub paths: PathContext, // wallpaper root and config directory for relative profile paths
+    pub config_sources: ConfigSources, // included files and which of them each profile entry came from
+}
+
+// A profile's pre_apply commands and their environment, so the GUI can run them on a worker
+// thread and only apply once they succeeded
+pub struct PreApplyHooks {
+    hooks: [Hooks; 2],
+    environment: Vec<(String, String)>,
+}
+
+impl PreApplyHooks {
+    // Blocks until every command has finished or timed out
+    pub fn run(&self) -> WallpaperResult<()> {
+        self.hooks.iter().try_for_each(|hooks| run_hooks(HookStage::PreApply, hooks, &self.environment))
+    }
+}
+
+// Everything the GUI needs to render a preview on a worker thread instead of the GTK one
+pub struct PreviewJob {
+    source: String, // expanded file or generator spec
+    overlays: Vec<Overlay>,
+    monitor_size: (u32, u32),
+    output: PathBuf, // separate from the files apply renders
+}
+
+impl PreviewJob {
+    // Renders at preview size, overlays shrink with the image so they look like they will on the monitor
+    pub fn render(&self) -> std::result::Result<String, String> {
+        let (width, height) = self.monitor_size;
+        let preview_height = (PREVIEW_WIDTH as u64 * height as u64 / width.max(1) as u64).max(1) as u32;
+
+        let mut image = if is_generator_spec(&self.source) {
+            GeneratorSpec::parse(&self.source)?.render(PREVIEW_WIDTH, preview_height)
+        } else {
+            image::open(&self.source)
+                .map_err(|e| format!("Failed to open {}: {}", self.source, e))?
+                .thumbnail(PREVIEW_WIDTH, preview_height)
+                .to_rgba8()
+        };
+
+        let scale = image.width() as f32 / width.max(1) as f32;
+        for overlay in &self.overlays {
+            let scaled = Overlay {
+                size: (overlay.size * scale).max(1.0),
+                margin: (overlay.margin as f32 * scale).round() as u32,
+                ..overlay.clone()
+            };
+            draw_overlay(&mut image, &scaled)?;
+        }
+
+        if let Some(parent) = self.output.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        }
+        image.save_with_format(&self.output, image::ImageFormat::Png)
+            .map_err(|e| format!("Failed to write {}: {}", self.output.display(), e))?;
+        Ok(self.output.to_string_lossy().to_string())
+    }
+}
+
+fn cache_file_name(profile_name: &str, device_name: &str, suffix: &str) -> String {
+    format!("{}_{}_{}.png", profile_name, device_name, suffix)
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.' { c } else { '_' })
+        .collect()
+}
+
+// Queues the entries due at `current` once per minute. The minute is checked and marked under one lock
+// that the thread started by a reload shares, so a reload never queues the same minute twice
+fn queue_due_entries(schedule: &[ScheduleEntry], current: (u32, u32), last_checked: &Mutex<Option<(u32, u32)>>,
+                     due_profiles: &Mutex<Vec<String>>) {
+    let mut last_checked = last_checked.lock().unwrap();
+    if *last_checked == Some(current) {
+        return;
+    }
+    *last_checked = Some(current);
+
+    for entry in schedule {
+        if entry.enabled && (entry.hour, entry.minute) == current {
+            info!("Time to apply profile: {}", entry.profile_name);
+            due_profiles.lock().unwrap().push(entry.profile_name.clone());
+        }
+    }
 }
 
 // Helper functions for Windows API
@@ -108,6 +258,18 @@ fn string_from_wide_ptr(ptr: *mut u16) -> String {
     }
 }
 
+// Picks the IDesktopWallpaper monitor a profile entry is set on: the same ID or display name,
+// then one containing the other (the bool is false then). None when nothing matches, setting
+// another monitor's wallpaper instead would silently overwrite it
+fn match_monitor_id(monitor_ids: &[(String, String)], device_name: &str) -> Option<(usize, bool)> {
+    monitor_ids.iter()
+        .position(|(display_name, monitor_id)| monitor_id == device_name || display_name == device_name)
+        .map(|index| (index, true))
+        .or_else(|| monitor_ids.iter()
+            .position(|(_, monitor_id)| monitor_id.contains(device_name) || device_name.contains(monitor_id.as_str()))
+            .map(|index| (index, false)))
+}
+
 unsafe extern "system" fn monitor_enum_proc(
     hmonitor: HMONITOR,
     _hdc_monitor: HDC,
@@ -144,12 +306,23 @@ impl WallpaperManager {
             profiles: HashMap::new(),
             schedule: Vec::new(),
             scheduler_running: Arc::new(AtomicBool::new(false)),
+            due_profiles: Arc::new(Mutex::new(Vec::new())),
+            scheduler_minute: Arc::new(Mutex::new(None)),
+            pending_retries: Vec::new(),
+            cache_dir: cache_dir(),
+            theme_templates: Vec::new(),
+            hooks: Hooks::default(),
+            hook_threads: HookThreads::default(),
+            config_document: ConfigFile::default(),
+            config_backups: DEFAULT_CONFIG_BACKUPS,
+            paths: PathContext::default(),
+            config_sources: ConfigSources::default(),
         };
         manager.refresh_monitors();
         manager
     }
 
-    fn refresh_monitors(&mut self) {
+    pub fn refresh_monitors(&mut self) {
         self.monitors.clear();
 
         unsafe {
@@ -168,30 +341,29 @@ impl WallpaperManager {
             self.monitors[i].device_name = wallpaper_monitor_id.to_string(); //stupid hack
         }
 
-        println!("\n=== Monitor Information ===");
-        println!("EnumDisplayMonitors found {} monitors:", self.monitors.len());
+        // Only shown with -v
+        debug!("EnumDisplayMonitors found {} monitors:", self.monitors.len());
 
         for (i, monitor) in self.monitors.iter().enumerate() {
-            println!("  {}. {}{} - {}x{}",
-                     i + 1,
-                     monitor.device_name,
-                     if monitor.is_primary { " (Primary)" } else { "" },
-                     monitor.rect.right - monitor.rect.left,
-                     monitor.rect.bottom - monitor.rect.top
+            debug!("  {}. {}{} - {}x{}",
+                   i + 1,
+                   monitor.device_name,
+                   if monitor.is_primary { " (Primary)" } else { "" },
+                   monitor.rect.right - monitor.rect.left,
+                   monitor.rect.bottom - monitor.rect.top
             );
         }
 
-        println!("\nIDesktopWallpaper found {} monitors:", wallpaper_monitor_ids.len());
+        debug!("IDesktopWallpaper found {} monitors:", wallpaper_monitor_ids.len());
         for (i, (display_name, wallpaper_monitor_id)) in wallpaper_monitor_ids.iter().enumerate() {
-            println!("  {}. Display: {}", i + 1, display_name);
-            println!("     Wallpaper ID: {}", wallpaper_monitor_id);
+            debug!("  {}. Display: {}", i + 1, display_name);
+            debug!("     Wallpaper ID: {}", wallpaper_monitor_id);
 
             let current_wallpaper = self.get_current_wallpaper_by_monitor_id(wallpaper_monitor_id);
             if !current_wallpaper.is_empty() {
-                println!("     Current wallpaper: {}", current_wallpaper);
+                debug!("     Current wallpaper: {}", current_wallpaper);
             }
         }
-        println!("===========================\n");
     }
 
     pub fn get_current_wallpaper_by_monitor_id(&self, monitor_id: &str) -> String {
@@ -282,8 +454,17 @@ impl WallpaperManager {
         monitor_ids
     }
 
-    fn set_wallpaper_for_monitor(&self, device_name: &str, wallpaper_path: &str) -> bool {
+    // Returns the monitor ID the wallpaper was set on and how, or the error of the last method tried
+    fn set_wallpaper_for_monitor(&self, device_name: &str, wallpaper_path: &str) -> WallpaperResult<(String, ApplyMethod)> {
         let wallpaper_path_wide = HSTRING::from(wallpaper_path);
+        let monitor_ids = self.get_desktop_wallpaper_monitor_ids();
+        let Some((index, _)) = match_monitor_id(&monitor_ids, device_name) else {
+            return Err(WallpaperError::MonitorNotFound {
+                device: device_name.to_string(),
+                available: monitor_ids.into_iter().map(|(_, monitor_id)| monitor_id).collect(),
+            });
+        };
+        let monitor_id_str = &monitor_ids[index].1;
 
         unsafe {
             let hr_init = CoInitialize(None);
@@ -295,65 +476,52 @@ impl WallpaperManager {
                 CLSCTX_ALL,
             );
 
-            let mut success = false;
+            let mut result = None;
+            let mut error = WallpaperError::Backend {
+                operation: "Creating IDesktopWallpaper".to_string(),
+                hresult: 0,
+            };
 
             if hr.is_ok() {
                 let wallpaper = hr.unwrap();
-                // Method 1: Try to find the correct monitor ID
-                let count_res: Result<u32> = wallpaper.GetMonitorDevicePathCount();
-                if count_res.is_ok() {
-                    let count = count_res.unwrap();
-
-                    for i in 0..count {
-                        let mut monitor_id_res: Result<PWSTR> = wallpaper.GetMonitorDevicePathAt(i);
-                        if monitor_id_res.is_ok()
-                        {
-                            let str_ptr = monitor_id_res.unwrap();
-                            let monitor_id_str = string_from_wide_ptr(str_ptr.0);
-
-                            // Check if this monitor matches our device name
-                            let is_match = monitor_id_str == device_name
-                                || monitor_id_str.contains(device_name)
-                                || device_name.contains(&monitor_id_str)
-                                || i == 0; // fallback to first monitor
-
-                            if is_match {
-                                println!("Trying to set wallpaper for monitor: {}", monitor_id_str);
-                                let hr = wallpaper.SetWallpaper(
-                                    str_ptr,
-                                    &wallpaper_path_wide,
-                                );
-
-                                match hr {
-                                    Ok(_) => {
-                                        println!("Successfully set wallpaper using monitor ID: {}", monitor_id_str);
-                                        success = true;
-                                        CoTaskMemFree(Some(str_ptr.0 as _));
-                                        break;
-                                    }
-                                    Err(E) => {
-                                        println!("Failed to set wallpaper, HRESULT: 0x{:X}", E.code().0);
-                                    }
-                                }
-                            }
+                // Method 1: Use the matched monitor ID
+                debug!("Trying to set wallpaper for monitor: {}", monitor_id_str);
+                let hr = wallpaper.SetWallpaper(
+                    &HSTRING::from(monitor_id_str.as_str()),
+                    &wallpaper_path_wide,
+                );
 
-                            CoTaskMemFree(Some(str_ptr.0 as _));
-                        }
+                match hr {
+                    Ok(_) => {
+                        debug!("Successfully set wallpaper using monitor ID: {}", monitor_id_str);
+                        result = Some((monitor_id_str.clone(), ApplyMethod::MonitorId));
+                    }
+                    Err(E) => {
+                        error = WallpaperError::Backend {
+                            operation: format!("SetWallpaper for monitor {}", monitor_id_str),
+                            hresult: E.code().0,
+                        };
                     }
                 }
 
                 // Method 2: Try using device name directly
-                if !success {
+                if result.is_none() {
                     let device_name_wide = HSTRING::from(device_name);
-                    println!("Trying direct device name: {}", device_name);
+                    debug!("Trying direct device name: {}", device_name);
                     let hr = wallpaper.SetWallpaper(
                         &device_name_wide,
                         &wallpaper_path_wide,
                     );
 
-                    if hr.is_ok() {
-                        println!("Successfully set wallpaper using direct device name");
-                        success = true;
+                    match hr {
+                        Ok(_) => {
+                            debug!("Successfully set wallpaper using direct device name");
+                            result = Some((device_name.to_string(), ApplyMethod::DeviceName));
+                        }
+                        Err(e) => error = WallpaperError::Backend {
+                            operation: format!("SetWallpaper for device name {}", device_name),
+                            hresult: e.code().0,
+                        },
                     }
                 }
 
@@ -370,13 +538,18 @@ impl WallpaperManager {
                 //         success = true;
                 //     }
                 // }
+            } else if let Err(e) = &hr {
+                error = WallpaperError::Backend {
+                    operation: "Creating IDesktopWallpaper".to_string(),
+                    hresult: e.code().0,
+                };
             }
 
             if com_initialized {
                 CoUninitialize();
             }
 
-            success
+            result.ok_or(error)
         }
     }
 
@@ -395,180 +568,815 @@ impl WallpaperManager {
         }
     }*/
 
-    fn set_wallpaper_for_monitor_with_fallback(&self, device_name: &str, wallpaper_path: &str) -> bool {
-        // First try the modern IDesktopWallpaper approach
-        if self.set_wallpaper_for_monitor(device_name, wallpaper_path) {
-            return true;
+    fn get_wallpaper_position(&self) -> Option<DesktopWallpaperPosition> {
+        unsafe {
+            let hr_init = CoInitialize(None);
+            let com_initialized = hr_init == HRESULT(0);
+
+            let hr: Result<IDesktopWallpaper> = CoCreateInstance(
+                &DesktopWallpaper,
+                None,
+                CLSCTX_ALL,
+            );
+
+            let position = hr.ok()
+                .and_then(|wallpaper| wallpaper.GetPosition().ok())
+                .and_then(|position| DesktopWallpaperPosition::from_value(position.0));
+
+            if com_initialized {
+                CoUninitialize();
+            }
+
+            position
+        }
+    }
+
+    fn set_wallpaper_position(&self, position: DesktopWallpaperPosition) -> WallpaperResult<()> {
+        unsafe {
+            let hr_init = CoInitialize(None);
+            let com_initialized = hr_init == HRESULT(0);
+
+            let hr: Result<IDesktopWallpaper> = CoCreateInstance(
+                &DesktopWallpaper,
+                None,
+                CLSCTX_ALL,
+            );
+
+            let result = match hr {
+                Ok(wallpaper) => match wallpaper.SetPosition(DESKTOP_WALLPAPER_POSITION(position as i32)) {
+                    Ok(_) => {
+                        info!("Set wallpaper position to {}", position.to_string());
+                        Ok(())
+                    }
+                    Err(E) => Err(WallpaperError::Backend {
+                        operation: "SetPosition".to_string(),
+                        hresult: E.code().0,
+                    }),
+                },
+                Err(e) => Err(WallpaperError::Backend {
+                    operation: "Creating IDesktopWallpaper".to_string(),
+                    hresult: e.code().0,
+                }),
+            };
+
+            if com_initialized {
+                CoUninitialize();
+            }
+
+            result
         }
+    }
+
+    fn set_wallpaper_for_monitor_with_fallback(&self, device_name: &str, wallpaper_path: &str) -> WallpaperResult<(String, ApplyMethod)> {
+        // First try the modern IDesktopWallpaper approach
+        let result = self.set_wallpaper_for_monitor(device_name, wallpaper_path);
 
         // If that fails, fall back to SystemParametersInfo
-        println!("IDesktopWallpaper failed for {}, using fallback method", device_name);
-        // self.set_wallpaper_fallback(wallpaper_path)
-        return false;
+        if result.is_err() {
+            warn!("IDesktopWallpaper failed for {}, using fallback method", device_name);
+            // self.set_wallpaper_fallback(wallpaper_path)
+        }
+        result
     }
 
-    pub fn print_monitors(&mut self) {
+    // Every monitor with its position and current wallpaper, left to right
+    pub fn list_monitors(&mut self) -> Vec<MonitorRecord> {
         self.refresh_monitors();
 
-        let wallpaper_monitor_ids = self.get_desktop_wallpaper_monitor_ids();
+        let mut monitors: Vec<&MonitorInfo> = self.monitors.iter().collect();
+        monitors.sort_by_key(|monitor| (monitor.rect.left, monitor.rect.top));
+        monitors.into_iter()
+            .map(|monitor| {
+                let wallpaper = self.get_current_wallpaper_by_monitor_id(&monitor.device_name);
+                MonitorRecord {
+                    id: monitor.device_name.clone(),
+                    rect: RectRecord {
+                        x: monitor.rect.left,
+                        y: monitor.rect.top,
+                        width: monitor.rect.right - monitor.rect.left,
+                        height: monitor.rect.bottom - monitor.rect.top,
+                    },
+                    primary: monitor.is_primary,
+                    wallpaper: if wallpaper.is_empty() { None } else { Some(wallpaper) },
+                }
+            })
+            .collect()
+    }
 
-        println!("Available monitors for wallpaper setting:");
-        println!("==========================================");
+    pub fn create_profile(&mut self, profile_name: &str) -> WallpaperResult<()> {
+        if self.profiles.contains_key(profile_name) {
+            return Err(WallpaperError::ProfileExists(profile_name.to_string()));
+        }
 
-        if wallpaper_monitor_ids.is_empty() {
-            println!("No monitors found via IDesktopWallpaper interface.");
-            println!("Using fallback EnumDisplayMonitors data:");
+        self.profiles.insert(profile_name.to_string(), WallpaperProfile::new(profile_name));
 
-            for (i, monitor) in self.monitors.iter().enumerate() {
-                println!("{}. {}{} - {}x{}",
-                         i + 1,
-                         monitor.device_name,
-                         if monitor.is_primary { " (Primary)" } else { "" },
-                         monitor.rect.right - monitor.rect.left,
-                         monitor.rect.bottom - monitor.rect.top
-                );
-                println!("   Use device name: {}\n", monitor.device_name);
-            }
+        info!("Profile '{}' created.", profile_name);
+        Ok(())
+    }
+
+    // With `relative_to_root` a path inside the wallpaper root is stored relative to it,
+    // so the profile keeps working on machines where the root lives somewhere else
+    pub fn set_wallpaper_in_profile(&mut self, profile_name: &str, device_name: &str, wallpaper_path: &str,
+                                    relative_to_root: bool) -> WallpaperResult<()> {
+        if !self.profiles.contains_key(profile_name) {
+            return Err(WallpaperError::ProfileNotFound(profile_name.to_string()));
+        }
+
+        let mut stored_path = wallpaper_path.to_string();
+        let wallpaper_path = self.paths.expand(wallpaper_path).map_err(WallpaperError::InvalidPath)?;
+        let wallpaper_path = wallpaper_path.as_str();
+
+        if is_generator_spec(wallpaper_path) {
+            // Generated wallpapers have no file on disk, only the spec needs to be valid
+            GeneratorSpec::parse(wallpaper_path).map_err(WallpaperError::InvalidProfile)?;
         } else {
-            for (i, (display_name, wallpaper_monitor_id)) in wallpaper_monitor_ids.iter().enumerate() {
-                // Find corresponding monitor info
-                let monitor_info = self.monitors.iter().find(|monitor| {
-                    if let Some(device_part) = monitor.device_name.strip_prefix("\\\\.\\") {
-                        wallpaper_monitor_id.contains(device_part)
-                    } else {
-                        false
-                    }
-                });
-
-                print!("{}. ", i + 1);
-                if let Some(info) = monitor_info {
-                    println!("{}{} - {}x{}",
-                             info.device_name,
-                             if info.is_primary { " (Primary)" } else { "" },
-                             info.rect.right - info.rect.left,
-                             info.rect.bottom - info.rect.top
-                    );
-                } else {
-                    println!("Monitor {}", i + 1);
+            // Verify file exists
+            if !Path::new(wallpaper_path).exists() {
+                return Err(WallpaperError::FileMissing(wallpaper_path.to_string()));
+            }
+
+            // Check if it's a supported image format
+            if let Some(extension) = Path::new(wallpaper_path).extension() {
+                let ext = extension.to_string_lossy().to_lowercase();
+                if !matches!(ext.as_str(), "jpg" | "jpeg" | "png" | "bmp" | "gif" | "tiff") {
+                    return Err(WallpaperError::UnsupportedFormat(ext));
                 }
+            }
+        }
+
+        // Verify device name exists
+        let device_found = self.monitors.iter().any(|monitor| monitor.device_name == device_name);
+        if !device_found {
+            return Err(WallpaperError::MonitorNotFound {
+                device: device_name.to_string(),
+                available: self.monitors.iter().map(|monitor| monitor.device_name.clone()).collect(),
+            });
+        }
+
+        if relative_to_root && !is_generator_spec(wallpaper_path) {
+            match self.paths.relative_to_root(wallpaper_path) {
+                Some(relative) => stored_path = relative,
+                None => match &self.paths.wallpaper_root {
+                    Some(root) => warn!("{} is not inside the wallpaper root {}, storing it as given", wallpaper_path, root),
+                    None => warn!("No wallpaper root is configured, storing {} as given", stored_path),
+                },
+            }
+        }
+
+        if let Some(profile) = self.profiles.get_mut(profile_name) {
+            profile.monitor_wallpapers.insert(device_name.to_string(), stored_path);
+            info!("Added wallpaper to profile '{}' for monitor {}", profile_name, device_name);
+
+            for warning in self.fit_warnings(device_name, wallpaper_path) {
+                warn!("{}", warning);
+            }
+        }
+        Ok(())
+    }
+
+    // Generated wallpapers are rendered at the monitor's resolution and never need a warning
+    pub fn fit_warnings(&self, device_name: &str, wallpaper_path: &str) -> Vec<FitWarning> {
+        if is_generator_spec(wallpaper_path) {
+            return Vec::new();
+        }
+
+        let wallpaper_path = match self.paths.expand(wallpaper_path) {
+            Ok(expanded) => expanded,
+            Err(_) => return Vec::new(),
+        };
+
+        match image::image_dimensions(&wallpaper_path) {
+            Ok(dimensions) => check_fit(dimensions, self.monitor_resolution(device_name)),
+            Err(_) => Vec::new(),
+        }
+    }
 
-                println!("   Use device name: {}", wallpaper_monitor_id);
+    // Ranks the images in `folder` for every monitor, best match first
+    pub fn suggest_best_match(&self, folder: &str) -> Vec<(String, Vec<(String, f64)>)> {
+        let candidates: Vec<(String, (u32, u32))> = images_in_folder(Path::new(folder))
+            .into_iter()
+            .filter_map(|path| image::image_dimensions(&path).ok().map(|dimensions| (path, dimensions)))
+            .collect();
+
+        self.monitors.iter()
+            .map(|monitor| {
+                let resolution = self.monitor_resolution(&monitor.device_name);
+                let mut ranked: Vec<(String, f64)> = candidates.iter()
+                    .map(|(path, dimensions)| (path.clone(), fit_score(*dimensions, resolution)))
+                    .collect();
+                ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+                (monitor.device_name.clone(), ranked)
+            })
+            .collect()
+    }
 
-                let current_wallpaper = self.get_current_wallpaper_by_monitor_id(wallpaper_monitor_id);
-                if !current_wallpaper.is_empty() {
-                    println!("   Current wallpaper: {}", current_wallpaper);
+    // Overlays may also be added on top of a wallpaper the profile inherits
+    pub fn add_overlay_to_profile(&mut self, profile_name: &str, device_name: &str, overlay: Overlay) -> WallpaperResult<()> {
+        let has_wallpaper = self.resolve_profile(profile_name)
+            .map(|resolved| resolved.profile.monitor_wallpapers.contains_key(device_name))
+            .unwrap_or(false);
+
+        match self.profiles.get_mut(profile_name) {
+            Some(profile) => {
+                if !has_wallpaper {
+                    return Err(WallpaperError::InvalidProfile(
+                        format!("Profile '{}' has no wallpaper for monitor {}", profile_name, device_name)));
                 }
-                println!();
+
+                profile.overlays.entry(device_name.to_string()).or_default().push(overlay);
+                info!("Added overlay to profile '{}' for monitor {}", profile_name, device_name);
+                Ok(())
             }
+            None => Err(WallpaperError::ProfileNotFound(profile_name.to_string())),
         }
+    }
 
-        println!("==========================================");
-        println!("Tip: Copy the 'Use device name' exactly when setting wallpapers.\n");
+    pub fn clear_overlays_in_profile(&mut self, profile_name: &str, device_name: &str) -> WallpaperResult<()> {
+        match self.profiles.get_mut(profile_name) {
+            Some(profile) => {
+                profile.overlays.remove(device_name);
+                Ok(())
+            }
+            None => Err(WallpaperError::ProfileNotFound(profile_name.to_string())),
+        }
     }
 
-    pub fn create_profile(&mut self, profile_name: &str) -> bool {
-        if self.profiles.contains_key(profile_name) {
-            println!("Profile '{}' already exists!", profile_name);
-            return false;
+    fn cache_path(&self, profile_name: &str, device_name: &str, suffix: &str) -> PathBuf {
+        self.cache_dir.join(cache_file_name(profile_name, device_name, suffix))
+    }
+
+    fn monitor_resolution(&self, device_name: &str) -> (u32, u32) {
+        self.monitors.iter()
+            .find(|monitor| monitor.device_name == device_name)
+            .map(|monitor| (
+                (monitor.rect.right - monitor.rect.left).max(1) as u32,
+                (monitor.rect.bottom - monitor.rect.top).max(1) as u32,
+            ))
+            .unwrap_or((1920, 1080))
+    }
+
+    // Returns the file that should actually be handed to the backend, rendering
+    // generated wallpapers and overlays into the cache if needed
+    fn prepare_wallpaper(&self, profile: &WallpaperProfile, device_name: &str, wallpaper_path: &str) -> std::result::Result<String, String> {
+        let mut source = self.paths.expand(wallpaper_path)
+            .map_err(|e| format!("Cannot resolve wallpaper for {}: {}", device_name, e))?;
+
+        if is_generator_spec(wallpaper_path) {
+            let (width, height) = self.monitor_resolution(device_name);
+            let output = self.cache_path(&profile.name, device_name, "generated");
+
+            render_generator(wallpaper_path, width, height, &output)
+                .map_err(|e| format!("Failed to generate wallpaper for {}: {}", device_name, e))?;
+            source = output.to_string_lossy().to_string();
         }
 
-        self.profiles.insert(profile_name.to_string(), WallpaperProfile {
-            name: profile_name.to_string(),
-            monitor_wallpapers: HashMap::new(),
-        });
+        let overlays = match profile.overlays.get(device_name) {
+            Some(overlays) if !overlays.is_empty() => overlays,
+            _ => return Ok(source),
+        };
+
+        let output = self.cache_path(&profile.name, device_name, "overlay");
+
+        render_overlays(&source, overlays, &output)
+            .map(|()| output.to_string_lossy().to_string())
+            .map_err(|e| format!("Failed to render overlays for {}: {}", device_name, e))
+    }
 
-        println!("Profile '{}' created.", profile_name);
-        true
+    // What the GUI needs to show a profile entry; rendering it does not need the manager
+    pub fn preview_job(&self, profile_name: &str, device_name: &str) -> Option<PreviewJob> {
+        let resolved = self.resolve_profile(profile_name).ok()?;
+        let wallpaper_path = resolved.profile.monitor_wallpapers.get(device_name)?;
+        let source = if is_generator_spec(wallpaper_path) {
+            wallpaper_path.clone()
+        } else {
+            self.paths.expand(wallpaper_path).ok()?
+        };
+
+        Some(PreviewJob {
+            source,
+            overlays: resolved.profile.overlays.get(device_name).cloned().unwrap_or_default(),
+            monitor_size: self.monitor_resolution(device_name),
+            output: self.cache_dir.join("preview").join(cache_file_name(profile_name, device_name, "preview")),
+        })
     }
 
-    pub fn set_wallpaper_in_profile(&mut self, profile_name: &str, device_name: &str, wallpaper_path: &str) -> bool {
+    pub fn resolve_profile(&self, profile_name: &str) -> WallpaperResult<ResolvedProfile> {
         if !self.profiles.contains_key(profile_name) {
-            println!("Profile '{}' not found!", profile_name);
-            return false;
+            return Err(WallpaperError::ProfileNotFound(profile_name.to_string()));
+        }
+        resolve_profile(&self.profiles, profile_name).map_err(WallpaperError::InvalidProfile)
+    }
+
+    // Makes `profile_name` inherit from `parent`, refusing parents that would create a cycle
+    pub fn set_profile_parent(&mut self, profile_name: &str, parent: Option<&str>) -> WallpaperResult<()> {
+        let previous = match self.profiles.get_mut(profile_name) {
+            Some(profile) => std::mem::replace(&mut profile.extends, parent.map(|parent| parent.to_string())),
+            None => return Err(WallpaperError::ProfileNotFound(profile_name.to_string())),
+        };
+
+        if let Err(e) = parent_chain(&self.profiles, profile_name) {
+            if let Some(profile) = self.profiles.get_mut(profile_name) {
+                profile.extends = previous;
+            }
+            return Err(WallpaperError::InvalidProfile(e));
         }
 
-        // Verify file exists
-        if !Path::new(wallpaper_path).exists() {
-            println!("Wallpaper file not found: {}", wallpaper_path);
-            return false;
+        Ok(())
+    }
+
+    pub fn apply_profile(&self, profile_name: &str) -> ApplyReport {
+        self.apply_devices(profile_name, None, true, true)
+    }
+
+    // For callers that ran pre_apply_hooks themselves; the post_apply hooks still run
+    pub fn apply_profile_after_hooks(&self, profile_name: &str) -> ApplyReport {
+        self.apply_devices(profile_name, None, true, false)
+    }
+
+    // Applies the monitors that failed in `report` again, and the fit mode if that failed,
+    // and returns the report with their new results
+    pub fn retry_failed(&self, report: &ApplyReport) -> ApplyReport {
+        let retry = self.apply_devices(&report.profile, Some(&report.failed_devices()), report.fit_failed(), false);
+        let mut merged = report.clone();
+        merged.merge(retry);
+        merged
+    }
+
+    // Global hooks run first, profile hooks without their own timeout use the global one
+    fn apply_hooks(&self, profile: &WallpaperProfile) -> [Hooks; 2] {
+        let profile_hooks = Hooks {
+            timeout: profile.hooks.timeout.or(self.hooks.timeout),
+            ..profile.hooks.clone()
+        };
+        [self.hooks.clone(), profile_hooks]
+    }
+
+    // Pre hooks see the wallpapers as configured, generators and overlays are not rendered yet
+    fn profile_pre_apply_hooks(&self, profile: &WallpaperProfile, profile_name: &str) -> PreApplyHooks {
+        let mut images: Vec<(String, String)> = profile.monitor_wallpapers.iter()
+            .map(|(device, path)| (device.clone(), self.paths.expand(path).unwrap_or_else(|_| path.clone())))
+            .collect();
+        images.sort();
+
+        PreApplyHooks {
+            hooks: self.apply_hooks(profile),
+            environment: hook_environment(HookStage::PreApply, profile_name, &images, None),
         }
+    }
 
-        // Check if it's a supported image format
-        if let Some(extension) = Path::new(wallpaper_path).extension() {
-            let ext = extension.to_string_lossy().to_lowercase();
-            if !matches!(ext.as_str(), "jpg" | "jpeg" | "png" | "bmp" | "gif" | "tiff") {
-                println!("Unsupported image format: {}", ext);
-                println!("Supported formats: jpg, jpeg, png, bmp, gif, tiff");
-                return false;
+    pub fn pre_apply_hooks(&self, profile_name: &str) -> WallpaperResult<PreApplyHooks> {
+        let resolved = self.resolve_profile(profile_name)?;
+        Ok(self.profile_pre_apply_hooks(&resolved.profile, profile_name))
+    }
+
+    fn spawn_post_apply_hooks(&self, profile: &WallpaperProfile, environment: Vec<(String, String)>) {
+        let hooks = self.apply_hooks(profile);
+        if hooks.iter().all(|hooks| hooks.post_apply.is_empty()) {
+            return;
+        }
+        self.hook_threads.spawn(move || {
+            for hooks in &hooks {
+                let _ = run_hooks(HookStage::PostApply, hooks, &environment);
+            }
+        });
+    }
+
+    pub fn wait_for_hooks(&self) {
+        self.hook_threads.wait();
+    }
+
+    // `devices` limits which profile entries are set, None sets all of them.
+    // Hooks only run around full applies, not around retries of single monitors.
+    fn apply_devices(&self, profile_name: &str, devices: Option<&[String]>, set_fit: bool, run_pre_apply: bool) -> ApplyReport {
+        let resolved = match self.resolve_profile(profile_name) {
+            Ok(resolved) => resolved,
+            Err(e) => {
+                error!("{}", e);
+                return ApplyReport::failed(profile_name, e.to_string());
+            }
+        };
+
+        let profile = &resolved.profile;
+        let mut report = ApplyReport::new(profile_name);
+        let mut applied_paths = Vec::new();
+
+        if devices.is_none() && run_pre_apply {
+            if let Err(e) = self.profile_pre_apply_hooks(profile, profile_name).run() {
+                error!("{}, profile '{}' not applied", e, profile_name);
+                return ApplyReport::cancelled(profile_name, e.to_string());
             }
         }
+        info!("Applying profile '{}'...", profile_name);
+
+        // Windows only keeps one position for all monitors
+        if let Some(fit) = profile.fit.filter(|_| set_fit) {
+            report.fit = Some(FitApplyResult {
+                position: fit.to_string().to_string(),
+                error: self.set_wallpaper_position(fit).err().map(|e| e.to_string()),
+            });
+        }
 
-        // Verify device name exists
-        let device_found = self.monitors.iter().any(|monitor| monitor.device_name == device_name);
-        if !device_found {
-            println!("Monitor device '{}' not found!", device_name);
-            println!("Available monitors:");
-            for monitor in &self.monitors {
-                println!("  {}", monitor.device_name);
+        let mut device_names: Vec<&String> = profile.monitor_wallpapers.keys()
+            .filter(|device_name| devices.is_none_or(|devices| devices.contains(device_name)))
+            .collect();
+        device_names.sort();
+
+        for device_name in device_names {
+            let wallpaper_path = &profile.monitor_wallpapers[device_name];
+            let mut result = MonitorApplyResult {
+                device: device_name.clone(),
+                path: wallpaper_path.clone(),
+                file: None,
+                monitor_id: None,
+                method: None,
+                error: None,
+            };
+
+            match self.prepare_wallpaper(profile, device_name, wallpaper_path) {
+                Ok(file) => match self.set_wallpaper_for_monitor_with_fallback(device_name, &file) {
+                    Ok((monitor_id, method)) => {
+                        info!("Set wallpaper for {}", device_name);
+                        applied_paths.push(file.clone());
+                        result.monitor_id = Some(monitor_id);
+                        result.method = Some(method);
+                        result.file = Some(file);
+                    }
+                    Err(e) => {
+                        warn!("Failed to set wallpaper for {}: {}", device_name, e);
+                        result.file = Some(file);
+                        result.error = Some(e.to_string());
+                    }
+                },
+                Err(e) => {
+                    warn!("{}", e);
+                    result.error = Some(e);
+                }
             }
-            return false;
+
+            report.monitors.push(result);
         }
 
-        if let Some(profile) = self.profiles.get_mut(profile_name) {
-            profile.monitor_wallpapers.insert(device_name.to_string(), wallpaper_path.to_string());
-            println!("Added wallpaper to profile '{}' for monitor {}", profile_name, device_name);
-            true
-        } else {
-            false
+        if !applied_paths.is_empty() {
+            self.export_theme(&applied_paths);
+        }
+
+        if devices.is_none() {
+            let images: Vec<(String, String)> = report.monitors.iter()
+                .filter(|monitor| monitor.error.is_none())
+                .filter_map(|monitor| Some((monitor.device.clone(), monitor.file.clone()?)))
+                .collect();
+            let environment = hook_environment(HookStage::PostApply, profile_name, &images, Some(report.success()));
+            self.spawn_post_apply_hooks(profile, environment);
+        }
+
+        report
+    }
+
+    // What apply_profile would do, without rendering anything or touching the desktop
+    pub fn plan_profile(&self, profile_name: &str) -> WallpaperResult<ApplyPlanRecord> {
+        let resolved = self.resolve_profile(profile_name)?;
+        let profile = &resolved.profile;
+
+        let monitor_ids = self.get_desktop_wallpaper_monitor_ids();
+
+        let mut plan = ApplyPlanRecord {
+            profile: profile_name.to_string(),
+            fit: profile.fit.map(|fit| FitChangeRecord {
+                current: self.get_wallpaper_position().map(|current| current.to_string().to_string()),
+                new: fit.to_string().to_string(),
+            }),
+            monitors: Vec::new(),
+        };
+
+        let mut devices: Vec<&String> = profile.monitor_wallpapers.keys().collect();
+        devices.sort();
+
+        for device_name in devices {
+            let wallpaper_path = &profile.monitor_wallpapers[device_name];
+            let matched = match_monitor_id(&monitor_ids, device_name);
+            let monitor = matched.map(|(index, _)| monitor_ids[index].1.clone());
+            let current = monitor.as_ref()
+                .map(|monitor_id| self.get_current_wallpaper_by_monitor_id(monitor_id))
+                .filter(|current| !current.is_empty());
+
+            // Same file as prepare_wallpaper would hand over, rendered ones always count as changed
+            let rendered = is_generator_spec(wallpaper_path) || profile.overlays.get(device_name).is_some_and(|overlays| !overlays.is_empty());
+            let new = match self.paths.expand(wallpaper_path) {
+                Ok(_) if rendered => {
+                    let suffix = if profile.overlays.get(device_name).is_some_and(|overlays| !overlays.is_empty()) { "overlay" } else { "generated" };
+                    Ok(self.cache_path(&profile.name, device_name, suffix).to_string_lossy().to_string())
+                }
+                Ok(expanded) if Path::new(&expanded).exists() => Ok(expanded),
+                Ok(expanded) => Err(format!("file not found: {}", expanded)),
+                Err(e) => Err(e),
+            };
+
+            let problem = match (&monitor, &new) {
+                (None, _) => Some("no monitor found".to_string()),
+                (_, Err(e)) => Some(e.clone()),
+                _ => None,
+            };
+            // Entries without a monitor are skipped by apply, nothing changes for them
+            let changed = match (&monitor, &current, &new) {
+                (None, _, _) => false,
+                (_, Some(current), Ok(new)) => rendered || !current.eq_ignore_ascii_case(new),
+                _ => true,
+            };
+
+            plan.monitors.push(PlannedWallpaperRecord {
+                device: device_name.clone(),
+                monitor,
+                exact_match: matched.is_some_and(|(_, exact)| exact),
+                current,
+                new: new.ok(),
+                changed,
+                problem,
+            });
+        }
+
+        Ok(plan)
+    }
+
+    // Local monitors left to right, then top to bottom, with their resolutions
+    fn monitors_by_position(&self) -> Vec<(String, (u32, u32))> {
+        let mut monitors: Vec<&MonitorInfo> = self.monitors.iter().collect();
+        monitors.sort_by_key(|monitor| (monitor.rect.left, monitor.rect.top));
+        monitors.into_iter()
+            .map(|monitor| (monitor.device_name.clone(), self.monitor_resolution(&monitor.device_name)))
+            .collect()
+    }
+
+    // Writes the resolved profile and every image it uses into a zip archive
+    pub fn export_profile(&self, profile_name: &str, output: &str) -> WallpaperResult<()> {
+        let resolved = self.resolve_profile(profile_name)?;
+
+        // Positions count every local monitor, so a profile for the right-hand monitor only stays on the right.
+        // Devices that are not connected come after all local monitors.
+        let local = self.monitors_by_position();
+        let mut devices: Vec<&String> = resolved.profile.monitor_wallpapers.keys().collect();
+        devices.sort_by_key(|device| (local.iter().position(|(name, _)| name == *device).unwrap_or(usize::MAX), device.to_string()));
+        let mut disconnected = 0;
+
+        let mut manifest = BundleManifest {
+            version: BUNDLE_VERSION,
+            profile: profile_name.to_string(),
+            fit: resolved.profile.fit.map(|fit| fit.to_string().to_string()),
+            monitors: Vec::new(),
+        };
+        let mut images: Vec<(String, String)> = Vec::new();
+
+        for device in devices {
+            let wallpaper_path = &resolved.profile.monitor_wallpapers[device];
+            let position = local.iter().position(|(name, _)| name == device).unwrap_or_else(|| {
+                disconnected += 1;
+                local.len() + disconnected - 1
+            });
+
+            let path = if is_generator_spec(wallpaper_path) {
+                wallpaper_path.clone()
+            } else {
+                let source = self.paths.expand(wallpaper_path).map_err(WallpaperError::InvalidPath)?;
+                if !Path::new(&source).exists() {
+                    return Err(WallpaperError::FileMissing(source));
+                }
+
+                // Monitors sharing an image share one copy in the archive
+                match images.iter().find(|(image, _)| *image == source) {
+                    Some((_, name)) => name.clone(),
+                    None => {
+                        let name = archive_name(images.len(), &source);
+                        images.push((source, name.clone()));
+                        name
+                    }
+                }
+            };
+
+            let (width, height) = self.monitor_resolution(device);
+            manifest.monitors.push(BundleMonitor {
+                device: device.clone(),
+                position,
+                width,
+                height,
+                path,
+                overlays: resolved.profile.overlays.get(device)
+                    .map(|overlays| overlays.iter().map(|overlay| overlay.to_spec()).collect())
+                    .unwrap_or_default(),
+            });
         }
+
+        write_bundle(Path::new(output), &manifest, &images).map_err(WallpaperError::Bundle)?;
+        info!("Exported profile '{}' with {} images to {}", profile_name, images.len(), output);
+        Ok(())
     }
 
-    pub fn apply_profile(&self, profile_name: &str) -> bool {
-        if let Some(profile) = self.profiles.get(profile_name) {
-            let mut success = true;
-            println!("Applying profile '{}'...", profile_name);
+    // Unpacks a bundle into <wallpaper root>/<profile>/ and maps its monitors onto the local
+    // ones. Paths are stored relative to the root when one is configured.
+    pub fn import_profile(&mut self, bundle: &str, profile_name: Option<&str>) -> WallpaperResult<()> {
+        let (manifest, mut archive) = open_bundle(Path::new(bundle)).map_err(WallpaperError::Bundle)?;
+
+        // Import it under another name with --name
+        let profile_name = profile_name.unwrap_or(&manifest.profile).to_string();
+        if self.profiles.contains_key(&profile_name) {
+            return Err(WallpaperError::ProfileExists(profile_name));
+        }
+
+        let folder_name: String = profile_name.chars()
+            .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+            .collect();
+        let destination = self.paths.base_dir().map_err(WallpaperError::InvalidPath)?.join(folder_name);
+        std::fs::create_dir_all(&destination).map_err(|e| WallpaperError::io(destination.display(), e))?;
+
+        let mut profile = WallpaperProfile::new(&profile_name);
+        profile.fit = manifest.fit.as_deref().and_then(DesktopWallpaperPosition::from_name);
+
+        let mapping = remap_devices(&manifest.monitors, &self.monitors_by_position());
+        for monitor in &manifest.monitors {
+            let device = match mapping.iter().find(|(bundled, _)| *bundled == monitor.device) {
+                Some((_, Some(device))) => device.clone(),
+                _ => {
+                    warn!("No local monitor left for {}, skipping it", monitor.device);
+                    continue;
+                }
+            };
+
+            let wallpaper_path = if is_archive_image(&monitor.path) {
+                let file_name = Path::new(&monitor.path).file_name().map(|name| name.to_os_string()).unwrap_or_default();
+                let target = destination.join(file_name);
+                extract_image(&mut archive, &monitor.path, &target).map_err(WallpaperError::Bundle)?;
+
+                let target = target.to_string_lossy().to_string();
+                self.paths.relative_to_root(&target).unwrap_or(target)
+            } else {
+                monitor.path.clone()
+            };
 
-            for (device_name, wallpaper_path) in &profile.monitor_wallpapers {
-                if !self.set_wallpaper_for_monitor_with_fallback(device_name, wallpaper_path) {
-                    println!("Failed to set wallpaper for {}", device_name);
-                    success = false;
-                } else {
-                    println!("Set wallpaper for {}", device_name);
+            for spec in &monitor.overlays {
+                match Overlay::parse(spec) {
+                    Ok(overlay) => profile.overlays.entry(device.clone()).or_default().push(overlay),
+                    Err(e) => warn!("Skipping overlay for {}: {}", device, e),
                 }
             }
 
-            success
-        } else {
-            println!("Profile '{}' not found!", profile_name);
-            false
+            info!("{} -> {}", monitor.device, device);
+            profile.monitor_wallpapers.insert(device, wallpaper_path);
         }
+
+        self.profiles.insert(profile_name.clone(), profile);
+        info!("Imported profile '{}' into {}", profile_name, destination.display());
+        Ok(())
     }
 
-    pub fn list_profiles(&self) -> Vec<String> {
-        if self.profiles.is_empty() {
-            println!("No profiles created.");
-            return Vec::new();
+    pub fn add_theme_template(&mut self, template_path: &str, output_path: &str) -> WallpaperResult<()> {
+        if !Path::new(template_path).exists() {
+            return Err(WallpaperError::FileMissing(template_path.to_string()));
+        }
+
+        self.theme_templates.push((template_path.to_string(), output_path.to_string()));
+        info!("Added theme template {} -> {}", template_path, output_path);
+        Ok(())
+    }
+
+    // Extracts a palette from the applied wallpapers and writes the built-in and user theme files
+    fn export_theme(&self, wallpaper_paths: &[String]) -> bool {
+        let palette = match extract_palette(wallpaper_paths, 8) {
+            Ok(palette) => palette,
+            Err(e) => {
+                warn!("Failed to extract color palette: {}", e);
+                return false;
+            }
+        };
+
+        let theme = Theme::from_palette(&wallpaper_paths[0], &palette);
+        match theme.export(&self.cache_dir.join("theme"), &self.theme_templates) {
+            Ok(written) => {
+                info!("Exported color theme to {} files", written.len());
+                true
+            }
+            Err(e) => {
+                warn!("Failed to export color theme: {}", e);
+                false
+            }
+        }
+    }
+
+    pub fn set_fit_in_profile(&mut self, profile_name: &str, fit: Option<DesktopWallpaperPosition>) -> WallpaperResult<()> {
+        match self.profiles.get_mut(profile_name) {
+            Some(profile) => {
+                profile.fit = fit;
+                Ok(())
+            }
+            None => Err(WallpaperError::ProfileNotFound(profile_name.to_string())),
+        }
+    }
+
+    pub fn list_profiles(&self) -> Vec<ProfileRecord> {
+        let mut profiles: Vec<ProfileRecord> = self.profiles.values()
+            .map(|profile| ProfileRecord {
+                name: profile.name.clone(),
+                extends: profile.extends.clone(),
+                fit: profile.fit.map(|fit| fit.to_string().to_string()),
+                wallpapers: profile.monitor_wallpapers.clone().into_iter().collect(),
+                overlays: profile.overlays.iter()
+                    .filter(|(_, overlays)| !overlays.is_empty())
+                    .map(|(device, overlays)| (device.clone(), overlays.iter().map(|overlay| overlay.to_spec()).collect()))
+                    .collect(),
+            })
+            .collect();
+        profiles.sort_by(|a, b| a.name.cmp(&b.name));
+        profiles
+    }
+
+    // Hashes every image referenced by a profile or found in `folders` and groups near-duplicates
+    pub fn find_duplicates(&self, folders: &[String], threshold: u32) -> Vec<DuplicateCluster> {
+        let mut paths: Vec<String> = self.profiles.values()
+            .flat_map(|profile| profile.monitor_wallpapers.values())
+            .filter(|path| !is_generator_spec(path))
+            .filter_map(|path| self.paths.expand(path).ok())
+            .filter(|path| Path::new(path).exists())
+            .collect();
+
+        for folder in folders {
+            paths.extend(images_in_folder(Path::new(folder)));
+        }
+
+        paths.sort();
+        paths.dedup();
+
+        info!("Hashing {} images...", paths.len());
+        find_duplicate_clusters(&paths, threshold)
+    }
+
+    pub fn print_duplicates(&self, clusters: &[DuplicateCluster]) {
+        if clusters.is_empty() {
+            println!("No duplicates found.");
+            return;
+        }
+
+        println!("Found {} groups of duplicates:", clusters.len());
+        for (i, cluster) in clusters.iter().enumerate() {
+            println!("{}.", i + 1);
+            for image in &cluster.images {
+                let used_by: Vec<&str> = self.profiles.values()
+                    .filter(|profile| profile.monitor_wallpapers.values()
+                        .any(|path| self.paths.expand(path).is_ok_and(|expanded| expanded == image.path)))
+                    .map(|profile| profile.name.as_str())
+                    .collect();
+
+                println!("   {} {} - {}x{}{}",
+                         if image.path == cluster.keep().path { "keep" } else { "    " },
+                         image.path,
+                         image.width,
+                         image.height,
+                         if used_by.is_empty() { String::new() } else { format!(" (used by {})", used_by.join(", ")) }
+                );
+            }
+        }
+    }
+
+    // Points every profile entry that uses a duplicate at the image suggested to keep.
+    // Entries that were stored relative to the wallpaper root stay relative if possible,
+    // ~ and $VARS entries get the absolute path of the kept image.
+    pub fn rewrite_duplicates(&mut self, clusters: &[DuplicateCluster]) -> usize {
+        let mut replacements = HashMap::new();
+        for cluster in clusters {
+            for image in &cluster.images[1..] {
+                replacements.insert(image.path.clone(), cluster.keep().path.clone());
+            }
         }
 
-        println!("Available profiles:");
-        let mut profile_names = Vec::new();
-        for (name, profile) in &self.profiles {
-            println!("- {} ({} monitors)", name, profile.monitor_wallpapers.len());
-            profile_names.push(name.clone());
+        let mut rewritten = 0;
+        let paths = &self.paths;
+        for profile in self.profiles.values_mut() {
+            for (device, path) in profile.monitor_wallpapers.iter_mut() {
+                let expanded = match paths.expand(path) {
+                    Ok(expanded) => expanded,
+                    Err(_) => continue,
+                };
+
+                if let Some(keep) = replacements.get(expanded.as_str()) {
+                    let root_relative = expand_vars(path).is_ok_and(|path| Path::new(&path).is_relative());
+                    let keep = if root_relative {
+                        paths.relative_to_root(keep).unwrap_or_else(|| keep.clone())
+                    } else {
+                        keep.clone()
+                    };
+                    info!("Profile '{}', monitor {}: {} -> {}", profile.name, device, path, keep);
+                    *path = keep;
+                    rewritten += 1;
+                }
+            }
         }
 
-        profile_names
+        rewritten
     }
 
-    pub fn add_schedule(&mut self, profile_name: &str, hour: u32, minute: u32) -> bool {
+    pub fn add_schedule(&mut self, profile_name: &str, hour: u32, minute: u32) -> WallpaperResult<()> {
         if !self.profiles.contains_key(profile_name) {
-            println!("Profile '{}' not found!", profile_name);
-            return false;
+            return Err(WallpaperError::ProfileNotFound(profile_name.to_string()));
         }
 
         if hour > 23 || minute > 59 {
-            println!("Invalid time format. Use 24-hour format (0-23 for hours, 0-59 for minutes).");
-            return false;
+            return Err(WallpaperError::InvalidTime { hour, minute });
         }
 
         self.schedule.push(ScheduleEntry {
@@ -578,60 +1386,101 @@ impl WallpaperManager {
             enabled: true,
         });
 
-        println!("Scheduled profile '{}' at {:02}:{:02}", profile_name, hour, minute);
-        true
+        info!("Scheduled profile '{}' at {:02}:{:02}", profile_name, hour, minute);
+        Ok(())
     }
 
-    pub fn list_schedule(&self) {
-        if self.schedule.is_empty() {
-            println!("No scheduled profiles.");
-            return;
-        }
-
-        println!("Scheduled profiles:");
-        for (i, entry) in self.schedule.iter().enumerate() {
-            println!("{}. {} at {:02}:{:02}{}",
-                     i + 1,
-                     entry.profile_name,
-                     entry.hour,
-                     entry.minute,
-                     if entry.enabled { " (enabled)" } else { " (disabled)" }
-            );
-        }
+    pub fn list_schedule(&self) -> Vec<ScheduleRecord> {
+        self.schedule.iter()
+            .map(|entry| ScheduleRecord {
+                profile: entry.profile_name.clone(),
+                hour: entry.hour,
+                minute: entry.minute,
+                enabled: entry.enabled,
+            })
+            .collect()
     }
 
     pub fn start_scheduler(&mut self) {
         if self.scheduler_running.load(Ordering::Relaxed) {
-            println!("Scheduler is already running.");
+            warn!("Scheduler is already running.");
             return;
         }
 
         self.scheduler_running.store(true, Ordering::Relaxed);
         let scheduler_running = self.scheduler_running.clone();
         let schedule = self.schedule.clone();
-        let profiles = self.profiles.clone();
+        let due_profiles = self.due_profiles.clone();
+        let scheduler_minute = self.scheduler_minute.clone();
 
+        // The monitor handles cannot leave this thread, so the scheduler only queues due
+        // profiles and apply_due_profiles applies them
         thread::spawn(move || {
             while scheduler_running.load(Ordering::Relaxed) {
                 let now = Local::now();
-                let current_hour = now.hour();
-                let current_minute = now.minute();
-
-                for entry in &schedule {
-                    if entry.enabled && entry.hour == current_hour && entry.minute == current_minute {
-                        // Apply profile logic would go here
-                        // We'd need to pass back to the main manager somehow
-                        println!("Time to apply profile: {}", entry.profile_name);
-                        // Sleep for a minute to avoid reapplying
-                        thread::sleep(Duration::from_secs(60));
-                    }
-                }
-
-                thread::sleep(Duration::from_secs(30));
+                queue_due_entries(&schedule, (now.hour(), now.minute()), &scheduler_minute, &due_profiles);
+                thread::sleep(Duration::from_secs(1));
             }
         });
 
-        println!("Scheduler started.");
+        info!("Scheduler started.");
+    }
+
+    // The running scheduler thread keeps its own copy of the schedule, so a successful
+    // reload stops it and starts a new one with a fresh flag the old thread cannot see.
+    // Both share scheduler_minute, an entry due in the minute of the reload is not queued again
+    pub fn reload_config(&mut self, filename: &str) -> WallpaperResult<()> {
+        let was_running = self.scheduler_running.load(Ordering::Relaxed);
+
+        // load_config leaves the current profiles untouched when the new file has errors
+        self.load_config(filename)?;
+
+        if was_running {
+            self.stop_scheduler();
+            self.scheduler_running = Arc::new(AtomicBool::new(false));
+            self.start_scheduler();
+        }
+
+        Ok(())
+    }
+
+    // Applies whatever the scheduler queued since the last call and retries the monitors
+    // that failed before, returns a report for every attempt
+    pub fn apply_due_profiles(&mut self) -> Vec<ApplyReport> {
+        let due: Vec<String> = self.due_profiles.lock().unwrap().drain(..).collect();
+        let mut reports = Vec::new();
+
+        // Retrying an older profile would overwrite the one that is due now
+        if !due.is_empty() {
+            self.pending_retries.clear();
+        }
+
+        let now = Instant::now();
+        for mut pending in std::mem::take(&mut self.pending_retries) {
+            if !pending.is_due(now) {
+                self.pending_retries.push(pending);
+                continue;
+            }
+
+            let report = self.retry_failed(&pending.report);
+            reports.push(report.clone());
+
+            if pending.record(report, now) {
+                self.pending_retries.push(pending);
+            } else if !pending.report.success() {
+                warn!("Giving up on profile '{}' after {} retries", pending.report.profile, pending.attempts);
+            }
+        }
+
+        for profile_name in due {
+            let report = self.apply_profile(&profile_name);
+            if report.retryable() {
+                self.pending_retries.push(PendingRetry::new(report.clone()));
+            }
+            reports.push(report);
+        }
+
+        reports
     }
 
     pub fn stop_scheduler(&mut self) {
@@ -640,130 +1489,211 @@ impl WallpaperManager {
         }
 
         self.scheduler_running.store(false, Ordering::Relaxed);
-        println!("Scheduler stopped.");
+        info!("Scheduler stopped.");
     }
 
-    pub fn save_config(&self, filename: &str) -> bool {
-        match std::fs::File::create(filename) {
-            Ok(mut file) => {
-                // Save profiles
-                if writeln!(file, "[PROFILES]").is_err() {
-                    println!("Failed to write to config file");
-                    return false;
-                }
+    // Files ending in .txt keep using the old line based format, everything else is TOML
+    // Profiles from included files and profiles.d are written back to the file they came
+    // from, and only files whose profiles actually changed are touched
+    pub fn save_config(&self, filename: &str) -> WallpaperResult<()> {
+        let mut files = Vec::new();
+        let serialize_error = |file: &str, message: String| WallpaperError::ConfigWrite {
+            file: file.to_string(),
+            message,
+        };
 
-                for (name, profile) in &self.profiles {
-                    if writeln!(file, "PROFILE:{}", name).is_err() {
-                        println!("Failed to write profile to config file");
-                        return false;
-                    }
-                    for (device, wallpaper) in &profile.monitor_wallpapers {
-                        if writeln!(file, "  {}={}", device, wallpaper).is_err() {
-                            println!("Failed to write wallpaper mapping to config file");
-                            return false;
-                        }
-                    }
+        if is_legacy_config(filename) {
+            files.push((filename.to_string(), write_legacy_config(&self.profiles, &self.schedule, &self.theme_templates)));
+        } else {
+            let content = serialize_config(&self.to_config_file(filename)).map_err(|e| serialize_error(filename, e))?;
+            files.push((filename.to_string(), content));
+
+            for included in &self.config_sources.included {
+                let profiles = profiles_for_file(&self.profiles, &self.config_sources, &included.filename, &included.document);
+                if serialize_profiles(&profiles) == included.saved {
+                    continue;
                 }
 
-                // Save schedule
-                if writeln!(file, "[SCHEDULE]").is_err() {
-                    println!("Failed to write schedule section to config file");
-                    return false;
+                let content = serialize_config(&ConfigFile { profiles, ..included.document.clone() })
+                    .map_err(|e| serialize_error(&included.filename, e))?;
+                files.push((included.filename.clone(), content));
+            }
+        }
+
+        // Every file is attempted, the first failure is reported
+        let mut result = Ok(());
+        for (file, mut content) in files {
+            if !is_legacy_config(&file) {
+                if let Ok(previous) = std::fs::read_to_string(&file) {
+                    content = preserve_formatting(&previous, &content);
                 }
+            }
 
-                for entry in &self.schedule {
-                    if writeln!(file, "{},{},{},{}",
-                                entry.profile_name,
-                                entry.hour,
-                                entry.minute,
-                                if entry.enabled { 1 } else { 0 }
-                    ).is_err() {
-                        println!("Failed to write schedule entry to config file");
-                        return false;
+            match write_atomically(&file, &content, self.config_backups) {
+                Ok(()) => info!("Configuration saved to {}", file),
+                Err(e) => {
+                    if result.is_ok() {
+                        result = Err(e);
                     }
                 }
+            }
+        }
 
-                println!("Configuration saved to {}", filename);
-                true
+        result
+    }
+
+    // Everything a reload depends on: the config itself, its includes and the drop-in directory
+    pub fn config_watch_paths(&self, filename: &str) -> Vec<WatchPath> {
+        let mut paths = vec![WatchPath::File(PathBuf::from(filename))];
+        paths.extend(self.config_sources.included.iter().map(|included| WatchPath::File(PathBuf::from(&included.filename))));
+        paths.push(WatchPath::Directory(PathContext::new(None, filename).config_dir.join(DROP_IN_DIR)));
+        paths
+    }
+
+    // Validates the whole file first and only replaces the current state when it has no errors
+    // Every diagnostic is printed, the first error is returned
+    pub fn load_config(&mut self, filename: &str) -> WallpaperResult<()> {
+        if !is_legacy_config(filename) {
+            if let Some(legacy) = legacy_config_for(filename) {
+                return self.migrate_legacy_config(&legacy, filename);
             }
-            Err(e) => {
-                println!("Failed to save config to {}: {}", filename, e);
-                false
+        }
+
+        let (loaded, diagnostics) = check_config_file(filename);
+        for diagnostic in &diagnostics {
+            match diagnostic.severity {
+                Severity::Error => error!("{}", diagnostic),
+                Severity::Warning => warn!("{}", diagnostic),
             }
         }
-    }
 
-    pub fn load_config(&mut self, filename: &str) -> bool {
-        let file = match File::open(filename) {
-            Ok(file) => file,
-            Err(_) => {
-                println!("Config file not found: {}", filename);
-                return false;
+        match loaded {
+            Some(loaded) if !has_errors(&diagnostics) => {
+                self.commit_config(loaded);
+                info!("Configuration loaded from {}", filename);
+                Ok(())
             }
-        };
+            _ => {
+                let first_error = diagnostics.iter().find(|diagnostic| diagnostic.severity == Severity::Error);
+                Err(WallpaperError::ConfigParse {
+                    file: first_error.map_or_else(|| filename.to_string(), |diagnostic| diagnostic.file.clone()),
+                    line: first_error.map_or(0, |diagnostic| diagnostic.line),
+                    message: first_error.map_or_else(|| "invalid configuration".to_string(), |diagnostic| diagnostic.message.clone()),
+                })
+            }
+        }
+    }
 
-        self.profiles.clear();
-        self.schedule.clear();
+    // Converts an old config.txt into the TOML file and keeps the original as config.txt.bak
+    fn migrate_legacy_config(&mut self, legacy: &Path, filename: &str) -> WallpaperResult<()> {
+        info!("Found legacy config {}, converting it to {}", legacy.display(), filename);
 
-        let reader = BufReader::new(file);
-        let mut current_section = String::new();
-        let mut current_profile = String::new();
+        self.load_config(&legacy.to_string_lossy())?;
+        self.save_config(filename)?;
 
-        for line in reader.lines() {
-            let line = match line {
-                Ok(line) => line.to_string(),
-                Err(_) => continue,
-            };
+        let backup = legacy.with_extension("txt.bak");
+        match std::fs::rename(legacy, &backup) {
+            Ok(()) => info!("Kept the old config as {}", backup.display()),
+            Err(e) => warn!("Failed to move {} to {}: {}", legacy.display(), backup.display(), e),
+        }
 
-            if line.is_empty() {
-                continue;
-            }
+        Ok(())
+    }
 
-            if line.starts_with('[') && line.ends_with(']') {
-                current_section = line[1..line.len()-1].to_string();
-                continue;
-            }
+    fn commit_config(&mut self, loaded: LoadedConfig) {
+        self.profiles = loaded.profiles;
+        self.schedule = loaded.schedule;
+        self.theme_templates = loaded.theme_templates;
+        self.hooks = loaded.hooks;
+        self.config_backups = loaded.document.backups.unwrap_or(DEFAULT_CONFIG_BACKUPS);
+        self.paths = loaded.paths;
+        self.config_sources = loaded.sources;
+        self.config_document = loaded.document;
+    }
 
-            match current_section.as_str() {
-                "PROFILES" => {
-                    if line.starts_with("PROFILE:") {
-                        current_profile = line[8..].to_string();
-                        self.profiles.insert(current_profile.clone(), WallpaperProfile {
-                            name: current_profile.clone(),
-                            monitor_wallpapers: HashMap::new(),
-                        });
-                    } else if line.starts_with("  ") && !current_profile.is_empty() {
-                        if let Some(eq_pos) = line.find('=') {
-                            let device = line[2..eq_pos].to_string();
-                            let wallpaper = line[eq_pos + 1..].to_string();
-                            if let Some(profile) = self.profiles.get_mut(&current_profile) {
-                                profile.monitor_wallpapers.insert(device, wallpaper);
-                            }
-                        }
-                    }
-                }
-                "SCHEDULE" => {
-                    let parts: Vec<&str> = line.split(',').collect();
-                    if parts.len() == 4 {
-                        if let (Ok(hour), Ok(minute), Ok(enabled_int)) = (
-                            parts[1].parse::<u32>(),
-                            parts[2].parse::<u32>(),
-                            parts[3].parse::<i32>()
-                        ) {
-                            self.schedule.push(ScheduleEntry {
-                                profile_name: parts[0].to_string(),
-                                hour,
-                                minute,
-                                enabled: enabled_int == 1,
-                            });
-                        }
-                    }
-                }
-                _ => {}
-            }
+    fn to_config_file(&self, filename: &str) -> ConfigFile {
+        let previous = &self.config_document;
+        let mut config = ConfigFile {
+            version: previous.version.max(CONFIG_VERSION),
+            backups: previous.backups,
+            wallpaper_root: self.paths.wallpaper_root.clone(),
+            include: previous.include.clone(),
+            hooks: previous.hooks.clone(),
+            extra: previous.extra.clone(),
+            ..ConfigFile::default()
+        };
+
+        for (template, output) in &self.theme_templates {
+            let extra = previous.templates.iter()
+                .find(|entry| entry.template.get_ref() == template && entry.output == *output)
+                .map(|entry| entry.extra.clone())
+                .unwrap_or_default();
+
+            config.templates.push(TemplateConfig {
+                template: unspanned(template.clone()),
+                output: output.clone(),
+                extra,
+            });
+        }
+
+        for entry in &self.schedule {
+            let extra = previous.schedule.iter()
+                .find(|previous_entry| *previous_entry.profile.get_ref() == entry.profile_name
+                    && *previous_entry.hour.get_ref() == entry.hour
+                    && *previous_entry.minute.get_ref() == entry.minute)
+                .map(|previous_entry| previous_entry.extra.clone())
+                .unwrap_or_default();
+
+            config.schedule.push(ScheduleConfig {
+                profile: unspanned(entry.profile_name.clone()),
+                hour: unspanned(entry.hour),
+                minute: unspanned(entry.minute),
+                enabled: entry.enabled,
+                extra,
+            });
         }
 
-        println!("Configuration loaded from {}", filename);
-        true
+        config.profiles = profiles_for_file(&self.profiles, &self.config_sources, filename, previous);
+
+        config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(profile_name: &str, hour: u32, minute: u32, enabled: bool) -> ScheduleEntry {
+        ScheduleEntry { profile_name: profile_name.to_string(), hour, minute, enabled }
+    }
+
+    #[test]
+    fn entries_without_a_matching_monitor_are_not_set_anywhere() {
+        let monitor_ids = vec![
+            ("\\\\.\\DISPLAY1".to_string(), "\\\\?\\DISPLAY#GSM5B08#1".to_string()),
+            ("Monitor 2".to_string(), "\\\\?\\DISPLAY#DEL40F6#2".to_string()),
+        ];
+
+        assert_eq!(match_monitor_id(&monitor_ids, "\\\\.\\DISPLAY1"), Some((0, true)));
+        assert_eq!(match_monitor_id(&monitor_ids, "\\\\?\\DISPLAY#DEL40F6#2"), Some((1, true)));
+        assert_eq!(match_monitor_id(&monitor_ids, "DEL40F6"), Some((1, false)));
+        assert_eq!(match_monitor_id(&monitor_ids, "\\\\.\\DISPLAY3"), None);
+        assert_eq!(match_monitor_id(&[], "\\\\.\\DISPLAY1"), None);
+    }
+
+    #[test]
+    fn a_restarted_scheduler_does_not_queue_the_same_minute_again() {
+        let schedule = vec![entry("work", 9, 0, true), entry("off", 9, 0, false), entry("night", 21, 0, true)];
+        let last_checked = Mutex::new(None);
+        let due = Mutex::new(Vec::new());
+
+        queue_due_entries(&schedule, (9, 0), &last_checked, &due);
+        // The thread started by a reload sees the minute the old one already handled
+        queue_due_entries(&schedule, (9, 0), &last_checked, &due);
+        assert_eq!(*due.lock().unwrap(), ["work"]);
+
+        queue_due_entries(&schedule, (9, 1), &last_checked, &due);
+        queue_due_entries(&schedule, (21, 0), &last_checked, &due);
+        assert_eq!(*due.lock().unwrap(), ["work", "night"]);
     }
-}
\ No newline at end of file
+}